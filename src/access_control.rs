@@ -0,0 +1,294 @@
+use crate::storage;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Role {
+    Admin,
+    User,
+}
+
+/// Entries configured through the environment. They are always present and
+/// cannot be removed with `/deny`, so a typo at runtime cannot lock out the
+/// operator.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct StaticAccess {
+    pub(crate) admin_user_ids: Vec<i64>,
+    pub(crate) user_ids: Vec<i64>,
+    pub(crate) chat_ids: Vec<i64>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct AccessEntries {
+    users: BTreeMap<i64, Role>,
+    chats: BTreeSet<i64>,
+}
+
+/// Users and chats allowed to use the bot. Runtime changes made through admin
+/// commands are persisted to a JSON file and merged with the static entries.
+pub(crate) struct AccessList {
+    path: PathBuf,
+    configured: StaticAccess,
+    runtime: Mutex<AccessEntries>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum AccessChange {
+    Granted,
+    AlreadyGranted,
+    Revoked,
+    NotListed,
+    /// The entry comes from the environment and can only be removed there.
+    Configured,
+}
+
+impl AccessList {
+    pub(crate) fn open(path: impl AsRef<Path>, configured: StaticAccess) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let runtime = storage::load_json(&path)?;
+        Ok(Self {
+            path,
+            configured,
+            runtime: Mutex::new(runtime),
+        })
+    }
+
+    /// Role of `user_id`, if the user is listed individually.
+    pub(crate) fn role(&self, user_id: i64) -> Option<Role> {
+        if self.configured.admin_user_ids.contains(&user_id) {
+            return Some(Role::Admin);
+        }
+        let runtime_role = self.runtime.lock().unwrap().users.get(&user_id).copied();
+        if runtime_role == Some(Role::Admin) || !self.configured.user_ids.contains(&user_id) {
+            return runtime_role;
+        }
+        Some(Role::User)
+    }
+
+    /// A message is allowed if either the sender or the chat it was sent in is listed.
+    pub(crate) fn is_allowed(&self, user_id: i64, chat_id: i64) -> bool {
+        self.role(user_id).is_some()
+            || self.configured.chat_ids.contains(&chat_id)
+            || self.runtime.lock().unwrap().chats.contains(&chat_id)
+    }
+
+    pub(crate) fn is_admin(&self, user_id: i64) -> bool {
+        self.role(user_id) == Some(Role::Admin)
+    }
+
+    pub(crate) fn has_admins(&self) -> bool {
+        !self.configured.admin_user_ids.is_empty()
+            || self
+                .runtime
+                .lock()
+                .unwrap()
+                .users
+                .values()
+                .any(|role| *role == Role::Admin)
+    }
+
+    /// Grants access to a user (positive ID) or a group chat (negative ID).
+    pub(crate) fn allow(&self, id: i64, role: Role) -> io::Result<AccessChange> {
+        let mut runtime = self.runtime.lock().unwrap();
+        let mut updated = runtime.clone();
+        let changed = if is_group_chat(id) {
+            updated.chats.insert(id)
+        } else {
+            updated.users.insert(id, role) != Some(role)
+        };
+        if !changed {
+            return Ok(AccessChange::AlreadyGranted);
+        }
+        storage::save_json(&self.path, &updated)?;
+        *runtime = updated;
+        Ok(AccessChange::Granted)
+    }
+
+    pub(crate) fn deny(&self, id: i64) -> io::Result<AccessChange> {
+        if self.configured.admin_user_ids.contains(&id)
+            || self.configured.user_ids.contains(&id)
+            || self.configured.chat_ids.contains(&id)
+        {
+            return Ok(AccessChange::Configured);
+        }
+
+        let mut runtime = self.runtime.lock().unwrap();
+        let mut updated = runtime.clone();
+        let changed = if is_group_chat(id) {
+            updated.chats.remove(&id)
+        } else {
+            updated.users.remove(&id).is_some()
+        };
+        if !changed {
+            return Ok(AccessChange::NotListed);
+        }
+        storage::save_json(&self.path, &updated)?;
+        *runtime = updated;
+        Ok(AccessChange::Revoked)
+    }
+}
+
+/// Telegram uses negative IDs for groups, supergroups and channels.
+fn is_group_chat(id: i64) -> bool {
+    id < 0
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum AdminCommand {
+    Allow { id: i64, role: Role },
+    Deny { id: i64 },
+}
+
+/// Parses `/allow <id> [admin]` and `/deny <id>`. Returns `None` for any other
+/// text and `Some(Err(usage))` for a recognised command with bad arguments.
+pub(crate) fn parse_admin_command(text: &str) -> Option<Result<AdminCommand, String>> {
+    let mut parts = text.split_whitespace();
+    // Commands sent in groups may be addressed as `/allow@BotName`; like
+    // other commands, the name is matched case-insensitively
+    let command = parts.next()?.split('@').next()?.to_ascii_lowercase();
+    let args: Vec<&str> = parts.collect();
+
+    match command.as_str() {
+        "/allow" => Some(match args.as_slice() {
+            [id] => parse_id(id).map(|id| AdminCommand::Allow {
+                id,
+                role: Role::User,
+            }),
+            [id, "admin"] => parse_id(id).map(|id| AdminCommand::Allow {
+                id,
+                role: Role::Admin,
+            }),
+            _ => Err("Usage: /allow <user or chat id> [admin]".to_string()),
+        }),
+        "/deny" => Some(match args.as_slice() {
+            [id] => parse_id(id).map(|id| AdminCommand::Deny { id }),
+            _ => Err("Usage: /deny <user or chat id>".to_string()),
+        }),
+        _ => None,
+    }
+}
+
+fn parse_id(value: &str) -> Result<i64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("'{}' is not a valid Telegram ID", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessChange, AccessList, AdminCommand, Role, StaticAccess, parse_admin_command};
+    use tempfile::TempDir;
+
+    fn configured() -> StaticAccess {
+        StaticAccess {
+            admin_user_ids: vec![1],
+            user_ids: vec![2],
+            chat_ids: vec![-100],
+        }
+    }
+
+    #[test]
+    fn configured_entries_grant_access_and_roles() {
+        let dir = TempDir::new().unwrap();
+        let acl = AccessList::open(dir.path().join("access.json"), configured()).unwrap();
+
+        assert_eq!(acl.role(1), Some(Role::Admin));
+        assert_eq!(acl.role(2), Some(Role::User));
+        assert_eq!(acl.role(3), None);
+        assert!(acl.is_allowed(3, -100));
+        assert!(!acl.is_allowed(3, 3));
+    }
+
+    #[test]
+    fn runtime_changes_are_persisted() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.json");
+
+        {
+            let acl = AccessList::open(&path, configured()).unwrap();
+            assert_eq!(acl.allow(5, Role::User).unwrap(), AccessChange::Granted);
+            assert_eq!(acl.allow(6, Role::Admin).unwrap(), AccessChange::Granted);
+            assert_eq!(acl.allow(-200, Role::User).unwrap(), AccessChange::Granted);
+            assert_eq!(
+                acl.allow(5, Role::User).unwrap(),
+                AccessChange::AlreadyGranted
+            );
+        }
+
+        let acl = AccessList::open(&path, configured()).unwrap();
+        assert_eq!(acl.role(5), Some(Role::User));
+        assert!(acl.is_admin(6));
+        assert!(acl.is_allowed(7, -200));
+
+        assert_eq!(acl.deny(5).unwrap(), AccessChange::Revoked);
+        assert_eq!(acl.deny(-200).unwrap(), AccessChange::Revoked);
+        assert_eq!(acl.deny(5).unwrap(), AccessChange::NotListed);
+        assert!(!acl.is_allowed(5, 5));
+        assert!(!acl.is_allowed(7, -200));
+    }
+
+    #[test]
+    fn configured_entries_cannot_be_denied_at_runtime() {
+        let dir = TempDir::new().unwrap();
+        let acl = AccessList::open(dir.path().join("access.json"), configured()).unwrap();
+
+        assert_eq!(acl.deny(1).unwrap(), AccessChange::Configured);
+        assert_eq!(acl.deny(-100).unwrap(), AccessChange::Configured);
+        assert!(acl.is_admin(1));
+    }
+
+    #[test]
+    fn configured_user_can_be_promoted_at_runtime() {
+        let dir = TempDir::new().unwrap();
+        let acl = AccessList::open(dir.path().join("access.json"), configured()).unwrap();
+
+        acl.allow(2, Role::Admin).unwrap();
+
+        assert!(acl.is_admin(2));
+    }
+
+    #[test]
+    fn admin_commands_are_parsed() {
+        assert_eq!(
+            parse_admin_command("/allow 42"),
+            Some(Ok(AdminCommand::Allow {
+                id: 42,
+                role: Role::User
+            }))
+        );
+        assert_eq!(
+            parse_admin_command("/allow@MyBot -1001 admin"),
+            Some(Ok(AdminCommand::Allow {
+                id: -1001,
+                role: Role::Admin
+            }))
+        );
+        assert_eq!(
+            parse_admin_command("/deny 42"),
+            Some(Ok(AdminCommand::Deny { id: 42 }))
+        );
+        assert!(matches!(parse_admin_command("/deny"), Some(Err(_))));
+        assert!(matches!(parse_admin_command("/allow abc"), Some(Err(_))));
+        assert_eq!(parse_admin_command("https://youtu.be/x"), None);
+    }
+
+    #[test]
+    fn admin_command_names_ignore_case() {
+        assert_eq!(
+            parse_admin_command("/Allow 42"),
+            Some(Ok(AdminCommand::Allow {
+                id: 42,
+                role: Role::User
+            }))
+        );
+        assert_eq!(
+            parse_admin_command("/DENY@MyBot 42"),
+            Some(Ok(AdminCommand::Deny { id: 42 }))
+        );
+        assert!(matches!(parse_admin_command("/ALLOW"), Some(Err(_))));
+    }
+}
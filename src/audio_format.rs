@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+
+/// Container/codec the downloaded audio is converted to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AudioFormat {
+    #[default]
+    Mp3,
+    M4a,
+    Opus,
+    Flac,
+    /// Best audio stream as published, extracted without re-encoding.
+    Original,
+}
+
+impl AudioFormat {
+    pub(crate) const ALL: [AudioFormat; 5] = [
+        AudioFormat::Mp3,
+        AudioFormat::M4a,
+        AudioFormat::Opus,
+        AudioFormat::Flac,
+        AudioFormat::Original,
+    ];
+
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "mp3" => Some(AudioFormat::Mp3),
+            "m4a" | "aac" => Some(AudioFormat::M4a),
+            "opus" => Some(AudioFormat::Opus),
+            "flac" => Some(AudioFormat::Flac),
+            "original" => Some(AudioFormat::Original),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::M4a => "m4a",
+            AudioFormat::Opus => "opus",
+            AudioFormat::Flac => "flac",
+            AudioFormat::Original => "original",
+        }
+    }
+
+    /// Value for yt-dlp's `--audio-format`; `best` keeps the source codec.
+    pub(crate) fn yt_dlp_audio_format(self) -> &'static str {
+        match self {
+            AudioFormat::Original => "best",
+            other => other.name(),
+        }
+    }
+}
+
+/// Bitrate preset applied when the audio is re-encoded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AudioQuality {
+    /// 64 kbit/s mono; small files for podcasts and talks.
+    Voice,
+    #[default]
+    Standard,
+    /// Highest quality the encoder offers.
+    Best,
+}
+
+impl AudioQuality {
+    pub(crate) const ALL: [AudioQuality; 3] = [
+        AudioQuality::Voice,
+        AudioQuality::Standard,
+        AudioQuality::Best,
+    ];
+
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "voice" => Some(AudioQuality::Voice),
+            "standard" => Some(AudioQuality::Standard),
+            "best" => Some(AudioQuality::Best),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            AudioQuality::Voice => "voice",
+            AudioQuality::Standard => "standard",
+            AudioQuality::Best => "best",
+        }
+    }
+
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            AudioQuality::Voice => "voice 64k mono",
+            AudioQuality::Standard => "standard 192k",
+            AudioQuality::Best => "best",
+        }
+    }
+
+    /// Extra yt-dlp arguments selecting this preset.
+    pub(crate) fn yt_dlp_args(self) -> &'static [&'static str] {
+        match self {
+            AudioQuality::Voice => &[
+                "--audio-quality",
+                "64K",
+                "--postprocessor-args",
+                "ExtractAudio:-ac 1",
+            ],
+            AudioQuality::Standard => &["--audio-quality", "192K"],
+            AudioQuality::Best => &["--audio-quality", "0"],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AudioFormat, AudioQuality};
+
+    #[test]
+    fn formats_round_trip_through_their_names() {
+        for format in AudioFormat::ALL {
+            assert_eq!(AudioFormat::parse(format.name()), Some(format));
+        }
+    }
+
+    #[test]
+    fn aliases_and_case_are_accepted() {
+        assert_eq!(AudioFormat::parse("AAC"), Some(AudioFormat::M4a));
+        assert_eq!(AudioFormat::parse("Opus"), Some(AudioFormat::Opus));
+        assert_eq!(AudioFormat::parse("wav"), None);
+    }
+
+    #[test]
+    fn original_keeps_source_codec() {
+        assert_eq!(AudioFormat::Original.yt_dlp_audio_format(), "best");
+        assert_eq!(AudioFormat::M4a.yt_dlp_audio_format(), "m4a");
+    }
+
+    #[test]
+    fn qualities_round_trip_through_their_names() {
+        for quality in AudioQuality::ALL {
+            assert_eq!(AudioQuality::parse(quality.name()), Some(quality));
+        }
+        assert_eq!(AudioQuality::parse("high"), None);
+    }
+
+    #[test]
+    fn voice_preset_downmixes_to_mono() {
+        let args = AudioQuality::Voice.yt_dlp_args();
+
+        assert!(
+            args.windows(2)
+                .any(|pair| pair == ["--audio-quality", "64K"])
+        );
+        assert!(args.contains(&"ExtractAudio:-ac 1"));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::chunk_audio::{ChunkInfo, split_at_times};
+use crate::config::Config;
+use crate::id3::{self, Tags};
+use crate::send_audio::{MediaInfo, SentFile, Upload, send_audio_to_telegram};
+use log::{error, info, warn};
+use serde_json::Value;
+use std::path::Path;
+use tokio::fs;
+
+/// A chapter from the `chapters` list of `yt-dlp -j` output.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Chapter {
+    pub(crate) title: String,
+    pub(crate) start_secs: f64,
+    pub(crate) end_secs: f64,
+}
+
+/// Chapters in playback order. A chapter without an end time runs until the
+/// next one, or the end of the video. Entries without a usable time range
+/// are dropped, and untitled ones are named by number. A single chapter is
+/// not worth splitting, so fewer than two give an empty list.
+pub(crate) fn parse_chapters(metadata: &Value) -> Vec<Chapter> {
+    let Some(entries) = metadata.get("chapters").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    let duration = metadata.get("duration").and_then(Value::as_f64);
+    let starts: Vec<Option<f64>> = entries
+        .iter()
+        .map(|entry| entry.get("start_time").and_then(Value::as_f64))
+        .collect();
+
+    let chapters: Vec<Chapter> = entries
+        .iter()
+        .enumerate()
+        .filter_map(|(i, entry)| {
+            let start_secs = starts[i]?;
+            let end_secs = entry
+                .get("end_time")
+                .and_then(Value::as_f64)
+                .or_else(|| starts.get(i + 1).copied().flatten())
+                .or(duration)?;
+            if end_secs <= start_secs {
+                return None;
+            }
+            let title = entry
+                .get("title")
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|title| !title.is_empty())
+                .map_or_else(|| format!("Chapter {}", i + 1), String::from);
+            Some(Chapter {
+                title,
+                start_secs,
+                end_secs,
+            })
+        })
+        .collect();
+
+    if chapters.len() < 2 {
+        return Vec::new();
+    }
+    chapters
+}
+
+/// Title a chapter is sent with, e.g. `03. Intro`.
+pub(crate) fn track_title(number: u32, chapter: &Chapter) -> String {
+    format!("{:02}. {}", number, chapter.title)
+}
+
+/// Cuts the download at its chapters. MP3 tracks get `tags` with the
+/// chapter's title and track number, and the video title as the album.
+/// `None` if the file could not be cut; it is then sent whole.
+pub(crate) async fn split(
+    path: &str,
+    chapters: &[Chapter],
+    tags: Option<&Tags>,
+) -> Option<Vec<ChunkInfo>> {
+    let ranges: Vec<(f64, f64)> = chapters
+        .iter()
+        .map(|chapter| (chapter.start_secs, chapter.end_secs))
+        .collect();
+    let tracks = match split_at_times(path, &ranges).await {
+        Ok(tracks) => tracks,
+        Err(e) => {
+            error!("Failed to split {} into chapters: {}", path, e);
+            return None;
+        }
+    };
+    info!("Split {} into {} chapters", path, tracks.len());
+
+    if let Some(tags) = tags {
+        for (track, chapter) in tracks.iter().zip(chapters) {
+            let track_tags = Tags {
+                title: Some(chapter.title.clone()),
+                album: tags.album.clone().or_else(|| tags.title.clone()),
+                track: Some(u64::from(track.index)),
+                ..tags.clone()
+            };
+            if let Err(e) = id3::write_tags(&track.path, &track_tags).await {
+                warn!(
+                    "Failed to write ID3 tags to {}: {}",
+                    track.path.display(),
+                    e
+                );
+            }
+        }
+    }
+    Some(tracks)
+}
+
+/// Sends each chapter track as its own audio message, then removes the
+/// original download once all tracks were delivered. Tracks over the upload
+/// limit are split by size like any other file. Returns the stored files like
+/// `send_audio_to_telegram`.
+pub(crate) async fn send_tracks(
+    upload: &Upload<'_>,
+    config: &Config,
+    original: &str,
+    tracks: &[ChunkInfo],
+    chapters: &[Chapter],
+    media: &MediaInfo<'_>,
+) -> Option<Vec<SentFile>> {
+    let total = tracks.len();
+    let mut delivered = true;
+    let mut files = Some(Vec::new());
+    for (track, chapter) in tracks.iter().zip(chapters) {
+        let title = track_title(track.index, chapter);
+        let caption = format!(
+            "Chapter {}/{} of {}\n{}",
+            track.index, total, media.title, media.caption
+        );
+        let track_media = MediaInfo {
+            title: &title,
+            caption: &caption,
+            duration: track.duration_secs,
+            ..*media
+        };
+        let Some(path) = track.path.to_str() else {
+            error!("Chapter file {} is not valid UTF-8", track.path.display());
+            delivered = false;
+            continue;
+        };
+        let track_label = format!("Uploading chapter {}/{}", track.index, total);
+        let track_upload = Upload {
+            label: &track_label,
+            ..*upload
+        };
+        match send_audio_to_telegram(&track_upload, config, path, &track_media).await {
+            // A track whose files are unknown makes the whole set unknown
+            Some(track_files) if track_files.is_empty() => files = None,
+            Some(track_files) => {
+                if let Some(files) = &mut files {
+                    files.extend(track_files);
+                }
+            }
+            None => delivered = false,
+        }
+    }
+
+    if !delivered {
+        return None;
+    }
+    if let Err(e) = fs::remove_file(Path::new(original)).await {
+        warn!("Failed to remove {}: {}", original, e);
+    }
+    Some(files.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chapter, parse_chapters, track_title};
+    use serde_json::json;
+
+    #[test]
+    fn chapters_fill_in_missing_ends_and_titles() {
+        let metadata = json!({
+            "duration": 600.0,
+            "chapters": [
+                { "start_time": 0.0, "end_time": 120.0, "title": "Intro" },
+                { "start_time": 120.0, "title": "  " },
+                { "start_time": 300.0, "title": "Outro" }
+            ]
+        });
+
+        assert_eq!(
+            parse_chapters(&metadata),
+            vec![
+                Chapter {
+                    title: "Intro".to_string(),
+                    start_secs: 0.0,
+                    end_secs: 120.0,
+                },
+                Chapter {
+                    title: "Chapter 2".to_string(),
+                    start_secs: 120.0,
+                    end_secs: 300.0,
+                },
+                Chapter {
+                    title: "Outro".to_string(),
+                    start_secs: 300.0,
+                    end_secs: 600.0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fewer_than_two_chapters_are_not_split() {
+        let single = json!({
+            "chapters": [{ "start_time": 0.0, "end_time": 60.0, "title": "All" }]
+        });
+        let empty_range = json!({
+            "chapters": [
+                { "start_time": 0.0, "end_time": 60.0, "title": "A" },
+                { "start_time": 60.0, "end_time": 60.0, "title": "B" }
+            ]
+        });
+
+        assert!(parse_chapters(&single).is_empty());
+        assert!(parse_chapters(&empty_range).is_empty());
+        assert!(parse_chapters(&json!({})).is_empty());
+    }
+
+    #[test]
+    fn track_titles_are_numbered() {
+        let chapter = Chapter {
+            title: "Intro".to_string(),
+            start_secs: 0.0,
+            end_secs: 1.0,
+        };
+
+        assert_eq!(track_title(3, &chapter), "03. Intro");
+    }
+}
@@ -1,6 +1,10 @@
+use log::warn;
+use std::io::SeekFrom;
 use std::path::{Path, PathBuf};
+use std::process::Stdio;
 use tokio::fs;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::process::Command;
 
 /// Metadata about a single chunk
 #[derive(Debug, Clone)]
@@ -9,6 +13,10 @@ pub struct ChunkInfo {
     #[allow(dead_code)]
     pub index: u32,
     pub size: u64,
+    /// Offset of the part in the original audio, when the split parsed frames
+    pub start_secs: Option<f64>,
+    /// Playing time of the part, when the split parsed frames
+    pub duration_secs: Option<f64>,
 }
 
 /// Custom error type for chunking operations
@@ -41,9 +49,6 @@ impl From<String> for ChunkError {
     }
 }
 
-/// The target size for each chunk (49MB to stay under Telegram's 50MB limit)
-const CHUNK_SIZE: u64 = 49 * 1024 * 1024; // 49MB
-
 /// Find the next MP3 frame sync marker (0xFFF) after a given position
 /// Returns the byte offset of the frame header, or None if not found
 /// Searches backwards from target position to find a safe split point
@@ -58,7 +63,7 @@ async fn find_next_frame_sync(
     let mut last_frame_pos: Option<u64> = None;
     let mut current_pos = search_start;
 
-    file.seek(std::io::SeekFrom::Start(search_start)).await?;
+    file.seek(SeekFrom::Start(search_start)).await?;
 
     while current_pos < target_pos {
         let remaining = (target_pos - current_pos).min(4096);
@@ -91,7 +96,7 @@ async fn find_next_frame_sync(
         // Move back 1 byte to catch patterns spanning buffer boundaries
         current_pos += n.saturating_sub(1) as u64;
         if n > 1 {
-            file.seek(std::io::SeekFrom::Start(current_pos)).await?;
+            file.seek(SeekFrom::Start(current_pos)).await?;
         } else {
             current_pos += 1;
         }
@@ -100,13 +105,62 @@ async fn find_next_frame_sync(
     Ok(last_frame_pos)
 }
 
-/// Check if a file needs to be split
+/// Largest file the Bot API accepts for upload
+pub const UPLOAD_LIMIT: u64 = 50 * 1024 * 1024; // 50MB
+
+/// Size limit and cut placement for the splitters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitConfig {
+    /// Largest file that may be uploaded; bigger files are split
+    pub upload_limit: u64,
+    pub mode: SplitMode,
+}
+
+impl Default for SplitConfig {
+    fn default() -> Self {
+        SplitConfig {
+            upload_limit: UPLOAD_LIMIT,
+            mode: SplitMode::Size,
+        }
+    }
+}
+
+impl SplitConfig {
+    /// Check if a file needs to be split
+    pub fn needs_chunking(&self, file_size: u64) -> bool {
+        file_size > self.upload_limit
+    }
+
+    /// The target size for each chunk: 49/50 of the limit, e.g. 49MB of 50MB
+    pub fn chunk_size(&self) -> u64 {
+        self.upload_limit / 50 * 49
+    }
+}
+
+/// Where `split_mp3_with` places the cut between two parts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SplitMode {
+    /// As late as the part size allows
+    #[default]
+    Size,
+    /// At the quietest moment shortly before the size boundary, so speech is
+    /// not cut mid-word
+    Silence,
+}
+
+/// Check if a file needs to be split for the default `UPLOAD_LIMIT`
 pub fn needs_chunking(file_size: u64) -> bool {
-    file_size > 50 * 1024 * 1024 // > 50MB
+    SplitConfig::default().needs_chunking(file_size)
 }
 
 /// Split an MP3 file into chunks
 ///
+/// Frames are parsed so every part ends on a frame boundary, starts with a
+/// copy of the ID3v2 tag, and carries its own Xing header with the part's
+/// frame count, so players show the right duration. A VBRI header is
+/// replaced by a Xing header. Files whose frames cannot be parsed are cut
+/// at byte offsets instead.
+///
 /// # Arguments
 /// * `file_path` - Path to the MP3 file to split
 ///
@@ -116,6 +170,19 @@ pub fn needs_chunking(file_size: u64) -> bool {
 /// # Errors
 /// Returns an error if file I/O fails
 pub async fn split_mp3(file_path: &str) -> Result<Vec<ChunkInfo>, ChunkError> {
+    split_mp3_with(file_path, SplitConfig::default()).await
+}
+
+/// Split an MP3 file into chunks for `config`'s upload limit, placing cuts
+/// according to its mode
+///
+/// See `split_mp3`. With `SplitMode::Silence` the audio before each size
+/// boundary is decoded with ffmpeg; if that fails the cut falls back to the
+/// size boundary.
+pub async fn split_mp3_with(
+    file_path: &str,
+    config: SplitConfig,
+) -> Result<Vec<ChunkInfo>, ChunkError> {
     let path = Path::new(file_path);
 
     // Verify file exists
@@ -129,22 +196,31 @@ pub async fn split_mp3(file_path: &str) -> Result<Vec<ChunkInfo>, ChunkError> {
     let metadata = fs::metadata(file_path).await?;
     let total_size = metadata.len();
 
-    if !needs_chunking(total_size) {
+    if !config.needs_chunking(total_size) {
         return Ok(vec![]); // No chunking needed
     }
 
-    let mut input_file = fs::File::open(file_path).await?;
+    match plan_mp3_chunks(file_path, total_size, config).await? {
+        Some(layout) => write_mp3_chunks(path, &layout).await,
+        None => split_by_bytes(path, total_size, config.chunk_size()).await,
+    }
+}
+
+/// Cut at byte offsets near `chunk_size`, moving each cut back to a frame
+/// sync marker when one is close. Parts after the first lack the tags.
+async fn split_by_bytes(
+    path: &Path,
+    total_size: u64,
+    chunk_size: u64,
+) -> Result<Vec<ChunkInfo>, ChunkError> {
+    let mut input_file = fs::File::open(path).await?;
     let mut chunks = Vec::new();
     let mut chunk_index = 1u32;
     let mut bytes_read = 0u64;
 
-    let file_stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("audio");
-    let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("mp3");
-    let parent_dir = path.parent().unwrap_or_else(|| Path::new("."));
-
     while bytes_read < total_size {
         // Calculate target end position for this chunk
-        let target_end = std::cmp::min(bytes_read + CHUNK_SIZE, total_size);
+        let target_end = std::cmp::min(bytes_read + chunk_size, total_size);
 
         // If this is the last chunk, just use the target end (no need for frame boundary)
         let is_last_chunk = target_end == total_size;
@@ -179,22 +255,17 @@ pub async fn split_mp3(file_path: &str) -> Result<Vec<ChunkInfo>, ChunkError> {
             break;
         }
 
-        let chunk_path = parent_dir.join(format!("{}_{}.{}", chunk_index, file_stem, extension));
+        let chunk_path = chunk_path(path, chunk_index, "mp3");
         let mut output_file = fs::File::create(&chunk_path).await?;
-
-        // Read and write the chunk
-        input_file
-            .seek(std::io::SeekFrom::Start(bytes_read))
-            .await?;
-        let mut buffer = vec![0u8; chunk_size as usize];
-        input_file.read_exact(&mut buffer).await?;
-        output_file.write_all(&buffer).await?;
+        copy_range(&mut input_file, &mut output_file, bytes_read, chunk_size).await?;
         output_file.sync_all().await?;
 
         chunks.push(ChunkInfo {
             path: chunk_path,
             index: chunk_index,
             size: chunk_size,
+            start_secs: None,
+            duration_secs: None,
         });
 
         bytes_read = actual_end;
@@ -204,6 +275,776 @@ pub async fn split_mp3(file_path: &str) -> Result<Vec<ChunkInfo>, ChunkError> {
     Ok(chunks)
 }
 
+/// `{parent}/{index}_{stem}.{ext}`, keeping the original extension if any
+fn chunk_path(path: &Path, index: u32, default_extension: &str) -> PathBuf {
+    let file_stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("audio");
+    let extension = path
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or(default_extension);
+    let parent_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    parent_dir.join(format!("{}_{}.{}", index, file_stem, extension))
+}
+
+/// Size of the buffer chunk data is streamed through, so memory use does not
+/// grow with the chunk size
+const COPY_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Copy `len` bytes starting at `start` from `input` to the end of `output`
+async fn copy_range(
+    input: &mut fs::File,
+    output: &mut fs::File,
+    start: u64,
+    len: u64,
+) -> Result<(), ChunkError> {
+    input.seek(SeekFrom::Start(start)).await?;
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE.min(len as usize)];
+    let mut remaining = len;
+    while remaining > 0 {
+        let want = buffer.len().min(remaining as usize);
+        let n = input.read(&mut buffer[..want]).await?;
+        if n == 0 {
+            return Err(ChunkError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+        output.write_all(&buffer[..n]).await?;
+        remaining -= n as u64;
+    }
+    output.flush().await?;
+    Ok(())
+}
+
+/// Length of the ID3v2 tag at the start of `header`, or 0 if there is none
+pub fn id3v2_tag_len(header: &[u8; 10]) -> u64 {
+    if &header[..3] != b"ID3" {
+        return 0;
+    }
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |size, byte| size << 7 | u64::from(byte & 0x7F));
+    // Version 2.4 tags may carry a 10-byte footer
+    let footer = if header[3] == 4 && header[5] & 0x10 != 0 {
+        10
+    } else {
+        0
+    };
+    10 + size + footer
+}
+
+/// How far past a damaged frame to look for the next one
+const RESYNC_WINDOW: u64 = 64 * 1024;
+
+/// Bytes read at a time while walking frame headers
+const SCAN_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Bit rates in kbit/s for MPEG-1 and MPEG-2/2.5 Layer III, by header index
+const MPEG1_BITRATES: [u32; 15] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const MPEG2_BITRATES: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+/// A parsed MPEG Layer III frame header
+#[derive(Debug, Clone, Copy)]
+struct FrameHeader {
+    bytes: [u8; 4],
+    mpeg1: bool,
+    mono: bool,
+    sample_rate: u32,
+    samples: u32,
+    len: u32,
+}
+
+impl FrameHeader {
+    /// Parse a header, rejecting reserved values, free-format bit rates and
+    /// layers other than III
+    fn parse(bytes: [u8; 4]) -> Option<Self> {
+        if bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+        let version = (bytes[1] >> 3) & 0x03;
+        let layer = (bytes[1] >> 1) & 0x03;
+        let bitrate_index = usize::from(bytes[2] >> 4);
+        let rate_index = usize::from((bytes[2] >> 2) & 0x03);
+        if version == 1
+            || layer != 1
+            || bitrate_index == 0
+            || bitrate_index == 15
+            || rate_index == 3
+        {
+            return None;
+        }
+
+        let mpeg1 = version == 3;
+        let kbps = if mpeg1 {
+            MPEG1_BITRATES[bitrate_index]
+        } else {
+            MPEG2_BITRATES[bitrate_index]
+        };
+        // MPEG-2 halves the MPEG-1 rates and MPEG-2.5 quarters them
+        let sample_rate = match version {
+            3 => [44100, 48000, 32000][rate_index],
+            2 => [22050, 24000, 16000][rate_index],
+            _ => [11025, 12000, 8000][rate_index],
+        };
+        let samples = if mpeg1 { 1152 } else { 576 };
+        let padding = u32::from((bytes[2] >> 1) & 0x01);
+
+        Some(FrameHeader {
+            bytes,
+            mpeg1,
+            mono: bytes[3] >> 6 == 3,
+            sample_rate,
+            samples,
+            len: samples / 8 * kbps * 1000 / sample_rate + padding,
+        })
+    }
+
+    /// Whether `other` belongs to the same stream
+    fn matches(&self, other: &FrameHeader) -> bool {
+        self.mpeg1 == other.mpeg1 && self.sample_rate == other.sample_rate
+    }
+
+    fn duration_secs(&self) -> f64 {
+        f64::from(self.samples) / f64::from(self.sample_rate)
+    }
+
+    /// Offset of the Xing header: after the header, CRC and side information
+    fn xing_offset(&self) -> usize {
+        let crc = if self.bytes[1] & 0x01 == 0 { 2 } else { 0 };
+        let side_info = match (self.mpeg1, self.mono) {
+            (true, true) => 17,
+            (true, false) => 32,
+            (false, true) => 9,
+            (false, false) => 17,
+        };
+        4 + crc + side_info
+    }
+}
+
+/// Buffered random access to the input while walking frame headers
+struct FrameReader {
+    file: fs::File,
+    buffer: Vec<u8>,
+    buffer_start: u64,
+}
+
+impl FrameReader {
+    /// Up to `len` bytes at `pos`; fewer at the end of the file
+    async fn read_at(&mut self, pos: u64, len: usize) -> Result<&[u8], ChunkError> {
+        let buffer_end = self.buffer_start + self.buffer.len() as u64;
+        if pos < self.buffer_start || pos + len as u64 > buffer_end {
+            self.file.seek(SeekFrom::Start(pos)).await?;
+            self.buffer.resize(SCAN_BUFFER_SIZE.max(len), 0);
+            let mut filled = 0;
+            while filled < self.buffer.len() {
+                let n = self.file.read(&mut self.buffer[filled..]).await?;
+                if n == 0 {
+                    break;
+                }
+                filled += n;
+            }
+            self.buffer.truncate(filled);
+            self.buffer_start = pos;
+        }
+        let offset = ((pos - self.buffer_start) as usize).min(self.buffer.len());
+        let end = (offset + len).min(self.buffer.len());
+        Ok(&self.buffer[offset..end])
+    }
+
+    async fn header_at(&mut self, pos: u64) -> Result<Option<FrameHeader>, ChunkError> {
+        let bytes = self.read_at(pos, 4).await?;
+        Ok(bytes.try_into().ok().and_then(FrameHeader::parse))
+    }
+
+    /// The first frame at or after `pos` whose successor, if any before
+    /// `end`, is also a frame of the same stream
+    async fn sync(&mut self, pos: u64, end: u64) -> Result<Option<(u64, FrameHeader)>, ChunkError> {
+        let search_end = (pos + RESYNC_WINDOW).min(end);
+        for candidate in pos..search_end {
+            let Some(header) = self.header_at(candidate).await? else {
+                continue;
+            };
+            let next = candidate + u64::from(header.len);
+            if next > end {
+                continue;
+            }
+            if next == end
+                || self
+                    .header_at(next)
+                    .await?
+                    .is_some_and(|next| header.matches(&next))
+            {
+                return Ok(Some((candidate, header)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The Xing or VBRI frame found before the audio, used as the template for
+/// each part's Xing frame
+struct InfoFrame {
+    header: FrameHeader,
+    /// `Xing` for VBR streams, `Info` for CBR ones
+    tag: [u8; 4],
+}
+
+/// A part of the audio, as a byte range of the original file
+struct PlannedChunk {
+    start: u64,
+    end: u64,
+    frames: u32,
+    start_secs: f64,
+    duration_secs: f64,
+    /// Xing seek table: position at each percent of the duration, in 1/256ths
+    /// of the part
+    toc: [u8; 100],
+}
+
+struct Mp3Layout {
+    /// Length of the ID3v2 tag copied into every part
+    tag_len: u64,
+    info_frame: Option<InfoFrame>,
+    chunks: Vec<PlannedChunk>,
+}
+
+/// Walk the frames of the MP3 at `file_path` and group them into parts that
+/// fit the chunk size with the tag and Xing frame added. `None` if most of
+/// the file is not MPEG Layer III frames.
+async fn plan_mp3_chunks(
+    file_path: &str,
+    total_size: u64,
+    config: SplitConfig,
+) -> Result<Option<Mp3Layout>, ChunkError> {
+    let mut reader = FrameReader {
+        file: fs::File::open(file_path).await?,
+        buffer: Vec::new(),
+        buffer_start: 0,
+    };
+
+    let tag_len = match <[u8; 10]>::try_from(reader.read_at(0, 10).await?) {
+        Ok(header) => id3v2_tag_len(&header).min(total_size),
+        Err(_) => 0,
+    };
+    // An ID3v1 tag occupies the last 128 bytes
+    let mut audio_end = total_size;
+    if total_size >= tag_len + 128 && reader.read_at(total_size - 128, 3).await? == b"TAG" {
+        audio_end -= 128;
+    }
+
+    let Some((first_frame, first_header)) = reader.sync(tag_len, audio_end).await? else {
+        return Ok(None);
+    };
+    let frame = reader
+        .read_at(first_frame, first_header.len as usize)
+        .await?;
+    let xing_offset = first_header.xing_offset();
+    let info_frame = match (frame.get(xing_offset..xing_offset + 4), frame.get(36..40)) {
+        (Some(b"Xing"), _) => Some(InfoFrame {
+            header: first_header,
+            tag: *b"Xing",
+        }),
+        (Some(b"Info"), _) => Some(InfoFrame {
+            header: first_header,
+            tag: *b"Info",
+        }),
+        (_, Some(b"VBRI")) => Some(InfoFrame {
+            header: first_header,
+            tag: *b"Xing",
+        }),
+        _ => None,
+    };
+    let info_len = info_frame
+        .as_ref()
+        .map_or(0, |info| u64::from(info.header.len));
+    // A tag this large would leave little room for audio in each part
+    let chunk_size = config.chunk_size();
+    if tag_len + info_len > chunk_size / 2 {
+        return Ok(None);
+    }
+    let budget = chunk_size - tag_len - info_len;
+
+    let mut chunks = Vec::new();
+    let mut pos = first_frame + info_len;
+    let mut chunk_start = pos;
+    let mut frame_offsets: Vec<u32> = Vec::new();
+    // Start time of each frame, relative to the part
+    let mut frame_starts: Vec<f64> = Vec::new();
+    let mut elapsed_secs = 0.0;
+    let mut chunk_secs = 0.0;
+    let mut covered = info_len;
+
+    while pos < audio_end {
+        let header = match reader.header_at(pos).await? {
+            Some(header) if pos + u64::from(header.len) <= audio_end => header,
+            _ => match reader.sync(pos, audio_end).await? {
+                Some((frame_pos, header)) => {
+                    pos = frame_pos;
+                    header
+                }
+                None => break,
+            },
+        };
+        let frame_end = pos + u64::from(header.len);
+
+        if frame_end - chunk_start > budget && !frame_offsets.is_empty() {
+            let cut = match config.mode {
+                SplitMode::Size => frame_offsets.len(),
+                SplitMode::Silence => {
+                    quiet_cut(&mut reader, chunk_start, pos, &frame_offsets, &frame_starts).await
+                }
+            };
+            let cut_offset = frame_offsets
+                .get(cut)
+                .copied()
+                .unwrap_or((pos - chunk_start) as u32);
+            let cut_secs = frame_starts.get(cut).copied().unwrap_or(chunk_secs);
+            chunks.push(close_chunk(
+                chunk_start,
+                chunk_start + u64::from(cut_offset),
+                &frame_offsets[..cut],
+                elapsed_secs,
+                cut_secs,
+                info_len,
+            ));
+
+            // Frames after the cut open the next part
+            elapsed_secs += cut_secs;
+            chunk_secs -= cut_secs;
+            chunk_start += u64::from(cut_offset);
+            frame_offsets.drain(..cut);
+            frame_offsets
+                .iter_mut()
+                .for_each(|offset| *offset -= cut_offset);
+            frame_starts.drain(..cut);
+            frame_starts.iter_mut().for_each(|start| *start -= cut_secs);
+        }
+
+        frame_offsets.push((pos - chunk_start) as u32);
+        frame_starts.push(chunk_secs);
+        chunk_secs += header.duration_secs();
+        covered += u64::from(header.len);
+        pos = frame_end;
+    }
+    if !frame_offsets.is_empty() {
+        chunks.push(close_chunk(
+            chunk_start,
+            pos,
+            &frame_offsets,
+            elapsed_secs,
+            chunk_secs,
+            info_len,
+        ));
+    }
+
+    // Mostly unparseable data is better split by size than misread
+    if covered < (audio_end - tag_len) / 10 * 9 {
+        return Ok(None);
+    }
+
+    Ok(Some(Mp3Layout {
+        tag_len,
+        info_frame,
+        chunks,
+    }))
+}
+
+/// How far before a size boundary to look for a quiet moment
+const SILENCE_WINDOW_SECS: f64 = 30.0;
+
+/// Sample rate the window is decoded at; plenty to measure loudness
+const ANALYSIS_SAMPLE_RATE: u32 = 8000;
+
+/// Length of audio around a candidate cut whose loudness is compared
+const QUIET_SPAN_SECS: f64 = 0.3;
+
+/// Index of the frame to start the next part at, so the part ends in the
+/// quietest moment of the last `SILENCE_WINDOW_SECS`. Falls back to
+/// `frame_offsets.len()`, the size boundary, if the window cannot be decoded.
+async fn quiet_cut(
+    reader: &mut FrameReader,
+    chunk_start: u64,
+    chunk_end: u64,
+    frame_offsets: &[u32],
+    frame_starts: &[f64],
+) -> usize {
+    let chunk_secs = frame_starts.last().copied().unwrap_or_default();
+    // Never cut before the first frame, which would leave an empty part
+    let first = frame_starts
+        .partition_point(|&start| start < chunk_secs - SILENCE_WINDOW_SECS)
+        .max(1);
+    if first >= frame_offsets.len() {
+        return frame_offsets.len();
+    }
+
+    let window_start = chunk_start + u64::from(frame_offsets[first]);
+    let pcm = match decode_window(reader, window_start, chunk_end).await {
+        Ok(pcm) => pcm,
+        Err(e) => {
+            warn!("Splitting at the size limit, could not find silence: {}", e);
+            return frame_offsets.len();
+        }
+    };
+
+    // The decoder's delay shifts the audio by a few milliseconds; negligible
+    // next to the span compared
+    let boundaries: Vec<f64> = frame_starts[first..]
+        .iter()
+        .map(|start| start - frame_starts[first])
+        .collect();
+    match quietest_boundary(&pcm, ANALYSIS_SAMPLE_RATE, &boundaries) {
+        Some(index) => first + index,
+        None => frame_offsets.len(),
+    }
+}
+
+/// Decode the frames in `start..end` to mono PCM at `ANALYSIS_SAMPLE_RATE`
+async fn decode_window(
+    reader: &mut FrameReader,
+    start: u64,
+    end: u64,
+) -> Result<Vec<i16>, ChunkError> {
+    let frames = reader
+        .read_at(start, (end - start) as usize)
+        .await?
+        .to_vec();
+
+    let mut child = Command::new("ffmpeg")
+        .args(["-v", "error", "-f", "mp3", "-i", "pipe:0"])
+        .args(["-ac", "1", "-ar", &ANALYSIS_SAMPLE_RATE.to_string()])
+        .args(["-f", "s16le", "pipe:1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // Feed stdin concurrently so a full stdout pipe cannot deadlock ffmpeg
+    let writer = tokio::spawn(async move {
+        let _ = stdin.write_all(&frames).await;
+    });
+    let output = child.wait_with_output().await?;
+    let _ = writer.await;
+
+    if !output.status.success() {
+        return Err(ChunkError::Message(format!(
+            "ffmpeg failed to decode: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output
+        .stdout
+        .chunks_exact(2)
+        .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+        .collect())
+}
+
+/// Index into `boundaries` (times in seconds from the start of `pcm`) of the
+/// one surrounded by the least energy over `QUIET_SPAN_SECS`. Later
+/// boundaries win ties, keeping parts as long as possible.
+pub fn quietest_boundary(pcm: &[i16], sample_rate: u32, boundaries: &[f64]) -> Option<usize> {
+    if pcm.is_empty() {
+        return None;
+    }
+    let half_span = (QUIET_SPAN_SECS * f64::from(sample_rate) / 2.0) as usize;
+
+    let mut quietest: Option<(usize, f64)> = None;
+    for (index, &time) in boundaries.iter().enumerate() {
+        let center = ((time * f64::from(sample_rate)) as usize).min(pcm.len() - 1);
+        let span = &pcm[center.saturating_sub(half_span)..(center + half_span).min(pcm.len())];
+        let energy = span
+            .iter()
+            .map(|&sample| f64::from(sample).powi(2))
+            .sum::<f64>()
+            / span.len() as f64;
+        if quietest.is_none_or(|(_, lowest)| energy <= lowest) {
+            quietest = Some((index, energy));
+        }
+    }
+    quietest.map(|(index, _)| index)
+}
+
+fn close_chunk(
+    start: u64,
+    end: u64,
+    frame_offsets: &[u32],
+    start_secs: f64,
+    duration_secs: f64,
+    info_len: u64,
+) -> PlannedChunk {
+    // Positions in the seek table count the Xing frame in front of the audio
+    let total = (end - start + info_len) as f64;
+    let mut toc = [0u8; 100];
+    for (percent, entry) in toc.iter_mut().enumerate() {
+        let frame = frame_offsets[percent * frame_offsets.len() / 100];
+        *entry = ((f64::from(frame) + info_len as f64) * 256.0 / total).min(255.0) as u8;
+    }
+    PlannedChunk {
+        start,
+        end,
+        frames: frame_offsets.len() as u32,
+        start_secs,
+        duration_secs,
+        toc,
+    }
+}
+
+/// A Xing frame describing a part of `frames` frames and `bytes` bytes,
+/// including the Xing frame itself. The seek table is left out when the
+/// template frame is too short for it.
+fn xing_frame(info: &InfoFrame, frames: u32, bytes: u32, toc: &[u8; 100]) -> Option<Vec<u8>> {
+    let mut header = info.header;
+    // The frame is written without a CRC
+    header.bytes[1] |= 0x01;
+    let mut frame = vec![0u8; header.len as usize];
+    frame[..4].copy_from_slice(&header.bytes);
+
+    let offset = header.xing_offset();
+    let with_toc = offset + 16 + toc.len() <= frame.len();
+    if offset + 16 > frame.len() {
+        return None;
+    }
+    let flags: u32 = if with_toc { 0x07 } else { 0x03 };
+    frame[offset..offset + 4].copy_from_slice(&info.tag);
+    frame[offset + 4..offset + 8].copy_from_slice(&flags.to_be_bytes());
+    frame[offset + 8..offset + 12].copy_from_slice(&frames.to_be_bytes());
+    frame[offset + 12..offset + 16].copy_from_slice(&bytes.to_be_bytes());
+    if with_toc {
+        frame[offset + 16..offset + 116].copy_from_slice(toc);
+    }
+    Some(frame)
+}
+
+/// Write each planned part as the ID3v2 tag, a fresh Xing frame and the
+/// part's audio frames
+async fn write_mp3_chunks(path: &Path, layout: &Mp3Layout) -> Result<Vec<ChunkInfo>, ChunkError> {
+    let mut input_file = fs::File::open(path).await?;
+    let mut chunks = Vec::new();
+
+    for (chunk_index, planned) in (1u32..).zip(&layout.chunks) {
+        let chunk_path = chunk_path(path, chunk_index, "mp3");
+        let mut output_file = fs::File::create(&chunk_path).await?;
+
+        copy_range(&mut input_file, &mut output_file, 0, layout.tag_len).await?;
+        let audio_len = planned.end - planned.start;
+        let xing = layout.info_frame.as_ref().and_then(|info| {
+            let bytes = audio_len + u64::from(info.header.len);
+            xing_frame(info, planned.frames, bytes as u32, &planned.toc)
+        });
+        if let Some(xing) = &xing {
+            output_file.write_all(xing).await?;
+        }
+        copy_range(&mut input_file, &mut output_file, planned.start, audio_len).await?;
+        output_file.sync_all().await?;
+
+        let size = layout.tag_len + xing.map_or(0, |xing| xing.len() as u64) + audio_len;
+        chunks.push(ChunkInfo {
+            path: chunk_path,
+            index: chunk_index,
+            size,
+            start_secs: Some(planned.start_secs),
+            duration_secs: Some(planned.duration_secs),
+        });
+    }
+
+    Ok(chunks)
+}
+
+/// Length of each part when splitting by time, chosen so that a part of
+/// average bitrate lands at 90% of `chunk_size`, leaving room for variance.
+pub fn segment_secs(chunk_size: u64, total_size: u64, duration_secs: f64) -> f64 {
+    if total_size == 0 {
+        return duration_secs.max(1.0);
+    }
+    let bytes_per_sec = total_size as f64 / duration_secs;
+    (chunk_size as f64 * 0.9 / bytes_per_sec).floor().max(1.0)
+}
+
+/// Read the duration of a media file with `ffprobe`
+pub async fn probe_duration(file_path: &str) -> Result<f64, ChunkError> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-show_entries", "format=duration"])
+        .args(["-of", "default=noprint_wrappers=1:nokey=1"])
+        .arg(file_path)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(ChunkError::Message(format!(
+            "ffprobe failed for {}: {}",
+            file_path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|_| ChunkError::Message(format!("ffprobe returned no duration for {}", file_path)))
+}
+
+/// Streams kept when segmenting: video files keep their picture, audio files
+/// drop embedded cover art, which cannot be segmented
+fn stream_maps(extension: &str) -> &'static [&'static str] {
+    if extension.eq_ignore_ascii_case("mp4") {
+        &["-map", "0:v:0", "-map", "0:a?"]
+    } else {
+        &["-map", "0:a"]
+    }
+}
+
+/// Split a file in any container ffmpeg can segment (M4A, Opus, FLAC, MP4, ...)
+/// into parts of equal duration, copying the streams without re-encoding
+///
+/// Used for formats whose frames `split_mp3` cannot parse. Parts are named
+/// like MP3 chunks: `1_{stem}.{ext}`, `2_{stem}.{ext}`, ...
+///
+/// # Errors
+/// Returns an error if ffmpeg fails or a part still exceeds the upload limit
+pub async fn split_by_duration(file_path: &str) -> Result<Vec<ChunkInfo>, ChunkError> {
+    split_by_duration_with(file_path, SplitConfig::default()).await
+}
+
+/// Split by duration for `config`'s upload limit; see `split_by_duration`
+pub async fn split_by_duration_with(
+    file_path: &str,
+    config: SplitConfig,
+) -> Result<Vec<ChunkInfo>, ChunkError> {
+    let path = Path::new(file_path);
+    if !path.exists() {
+        return Err(ChunkError::Message(format!(
+            "File not found: {}",
+            file_path
+        )));
+    }
+
+    let total_size = fs::metadata(file_path).await?.len();
+    if !config.needs_chunking(total_size) {
+        return Ok(vec![]);
+    }
+
+    let duration = probe_duration(file_path).await?;
+    let segment_secs = segment_secs(config.chunk_size(), total_size, duration);
+
+    let file_stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("audio");
+    let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("audio");
+    let parent_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    // `%` starts a placeholder in ffmpeg's segment pattern
+    let pattern = parent_dir.join(format!("%d_{}.{}", file_stem.replace('%', "%%"), extension));
+
+    let status = Command::new("ffmpeg")
+        .args(["-v", "error", "-y", "-i"])
+        .arg(file_path)
+        .args(stream_maps(extension))
+        .args(["-c", "copy", "-f", "segment"])
+        .args(["-segment_time", &segment_secs.to_string()])
+        .args(["-segment_start_number", "1", "-reset_timestamps", "1"])
+        .arg(&pattern)
+        .status()
+        .await?;
+
+    let mut chunks = Vec::new();
+    for index in 1u32.. {
+        let chunk_path = parent_dir.join(format!("{}_{}.{}", index, file_stem, extension));
+        let Ok(metadata) = fs::metadata(&chunk_path).await else {
+            break;
+        };
+        chunks.push(ChunkInfo {
+            path: chunk_path,
+            index,
+            size: metadata.len(),
+            start_secs: None,
+            duration_secs: None,
+        });
+    }
+
+    if !status.success() {
+        cleanup_chunks(chunks).await?;
+        return Err(ChunkError::Message(format!(
+            "ffmpeg failed to split {}",
+            file_path
+        )));
+    }
+
+    if let Some(oversized) = chunks
+        .iter()
+        .find(|chunk| config.needs_chunking(chunk.size))
+    {
+        let message = format!(
+            "Part {} of {} is still {}MB",
+            oversized.index,
+            file_path,
+            oversized.size / 1024 / 1024
+        );
+        cleanup_chunks(chunks).await?;
+        return Err(ChunkError::Message(message));
+    }
+
+    Ok(chunks)
+}
+
+/// Cut a file into the given `(start, end)` time ranges, in seconds, copying
+/// the streams without re-encoding
+///
+/// Parts are named like other chunks: `1_{stem}.{ext}`, `2_{stem}.{ext}`, ...
+/// Unlike the other splitters, parts are not held to the upload limit.
+///
+/// # Errors
+/// Returns an error if ffmpeg fails for any range; parts already written are
+/// removed
+pub async fn split_at_times(
+    file_path: &str,
+    ranges: &[(f64, f64)],
+) -> Result<Vec<ChunkInfo>, ChunkError> {
+    let path = Path::new(file_path);
+    if !path.exists() {
+        return Err(ChunkError::Message(format!(
+            "File not found: {}",
+            file_path
+        )));
+    }
+    let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("audio");
+
+    let mut chunks = Vec::new();
+    for (index, &(start, end)) in (1u32..).zip(ranges) {
+        let chunk_path = chunk_path(path, index, "audio");
+        let status = Command::new("ffmpeg")
+            .args(["-v", "error", "-y", "-ss", &start.to_string(), "-i"])
+            .arg(file_path)
+            .args(["-t", &(end - start).to_string()])
+            .args(stream_maps(extension))
+            .args(["-c", "copy"])
+            .arg(&chunk_path)
+            .status()
+            .await;
+
+        let metadata = match status {
+            Ok(status) if status.success() => fs::metadata(&chunk_path).await,
+            Ok(_) => Err(std::io::Error::other("ffmpeg failed")),
+            Err(e) => Err(e),
+        };
+        match metadata {
+            Ok(metadata) => chunks.push(ChunkInfo {
+                path: chunk_path,
+                index,
+                size: metadata.len(),
+                start_secs: Some(start),
+                duration_secs: Some(end - start),
+            }),
+            Err(e) => {
+                let _ = fs::remove_file(&chunk_path).await;
+                cleanup_chunks(chunks).await?;
+                return Err(ChunkError::Message(format!(
+                    "Failed to cut part {} of {}: {}",
+                    index, file_path, e
+                )));
+            }
+        }
+    }
+
+    Ok(chunks)
+}
+
 /// Clean up chunk files
 pub async fn cleanup_chunks(chunks: Vec<ChunkInfo>) -> Result<(), ChunkError> {
     for chunk in chunks {
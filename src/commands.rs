@@ -0,0 +1,337 @@
+use crate::AppState;
+use crate::access_control::{self, AccessChange, AdminCommand};
+use crate::audio_format::{AudioFormat, AudioQuality};
+use crate::download_options;
+use crate::format_choice::{self, Choice};
+use crate::jobs::JobState;
+use crate::playlist;
+use crate::telegram_status::TelegramStatusMessage;
+use crate::types::TelegramMessage;
+use crate::worker_pool::Cancellation;
+use log::{error, info, warn};
+
+const HELP_TEXT: &str = "Send me a link and pick audio or video from the buttons below my reply. \
+Add a format, quality or video height after the link to skip the buttons, \
+e.g. <link> opus voice or <link> 480p. Add playlist to download every entry of a playlist link, \
+or chapters to get each chapter of a video as its own track.
+
+Commands:
+/format <mp3|m4a|opus|flac|original> - set your default format
+/quality <voice|standard|best> - set your default quality
+/status - show your active and queued downloads
+/cancel - cancel all of your downloads
+/cancel <id> - cancel one download
+/help - show this message";
+
+const ADMIN_HELP_TEXT: &str = "
+Admin commands:
+/allow <id> [admin] - allow a user (positive ID) or chat (negative ID)
+/deny <id> - remove a user or chat";
+
+/// A slash command at the start of a message, e.g. `/cancel 12`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct BotCommand<'a> {
+    /// Lowercase command name without the leading `/` or a `@BotName` suffix.
+    pub(crate) name: String,
+    pub(crate) args: &'a str,
+    pub(crate) text: &'a str,
+}
+
+/// Returns the command the message starts with, based on the `bot_command`
+/// entity Telegram attaches to it.
+pub(crate) fn parse_command(message: &TelegramMessage) -> Option<BotCommand<'_>> {
+    let text = message.text.as_deref()?;
+    let entity = message
+        .entities
+        .iter()
+        .find(|entity| entity.kind == "bot_command" && entity.offset == 0)?;
+
+    // Entity lengths are in UTF-16 code units; convert to a byte offset.
+    let mut utf16_len = 0;
+    let end = text
+        .char_indices()
+        .find(|(_, c)| {
+            let reached = utf16_len >= entity.length;
+            utf16_len += c.len_utf16();
+            reached
+        })
+        .map_or(text.len(), |(index, _)| index);
+
+    let command = text[..end].strip_prefix('/')?;
+    let name = command.split('@').next()?.to_ascii_lowercase();
+    Some(BotCommand {
+        name,
+        args: text[end..].trim(),
+        text,
+    })
+}
+
+pub(crate) async fn handle_command(
+    state: &AppState,
+    user_id: i64,
+    chat_id: i64,
+    command: BotCommand<'_>,
+) {
+    let reply = match command.name.as_str() {
+        "start" | "help" => {
+            if state.access.is_admin(user_id) {
+                format!("{}\n{}", HELP_TEXT, ADMIN_HELP_TEXT)
+            } else {
+                HELP_TEXT.to_string()
+            }
+        }
+        "format" => set_format(state, user_id, command.args),
+        "quality" => set_quality(state, user_id, command.args),
+        "status" => status_text(state, user_id),
+        "cancel" => cancel(state, user_id, command.args).await,
+        "allow" | "deny" => {
+            let admin_command = access_control::parse_admin_command(command.text)
+                .unwrap_or_else(|| Err(ADMIN_HELP_TEXT.trim_start().to_string()));
+            admin_reply(state, user_id, admin_command)
+        }
+        other => format!("Unknown command /{}. Send /help for usage.", other),
+    };
+
+    TelegramStatusMessage::create(&state.telegram, chat_id, &reply).await;
+}
+
+fn set_format(state: &AppState, user_id: i64, args: &str) -> String {
+    if args.is_empty() {
+        return format!(
+            "Your default format is {}. Available: {}",
+            state.preferences.get(user_id).format.name(),
+            download_options::format_names()
+        );
+    }
+
+    let Some(format) = AudioFormat::parse(args) else {
+        return format!(
+            "Unknown format '{}'. Available: {}",
+            args,
+            download_options::format_names()
+        );
+    };
+
+    match state
+        .preferences
+        .update(user_id, |options| options.format = format)
+    {
+        Ok(_) => format!("Default format set to {}.", format.name()),
+        Err(e) => {
+            error!("Failed to save preferences for user {}: {}", user_id, e);
+            "Failed to save your preferences.".to_string()
+        }
+    }
+}
+
+fn set_quality(state: &AppState, user_id: i64, args: &str) -> String {
+    if args.is_empty() {
+        return format!(
+            "Your default quality is {}. Available: {}",
+            state.preferences.get(user_id).quality.label(),
+            download_options::quality_names()
+        );
+    }
+
+    let Some(quality) = AudioQuality::parse(args) else {
+        return format!(
+            "Unknown quality '{}'. Available: {}",
+            args,
+            download_options::quality_names()
+        );
+    };
+
+    match state
+        .preferences
+        .update(user_id, |options| options.quality = quality)
+    {
+        Ok(_) => format!("Default quality set to {}.", quality.label()),
+        Err(e) => {
+            error!("Failed to save preferences for user {}: {}", user_id, e);
+            "Failed to save your preferences.".to_string()
+        }
+    }
+}
+
+fn status_text(state: &AppState, user_id: i64) -> String {
+    let jobs: Vec<_> = state
+        .jobs
+        .unfinished()
+        .into_iter()
+        .filter(|job| job.user_id == user_id)
+        .collect();
+
+    if jobs.is_empty() {
+        return "You have no active or queued downloads.".to_string();
+    }
+
+    let mut text = String::from("Your downloads:");
+    for job in jobs {
+        let state_text = match state.pool.queue_position(job.id) {
+            Some(position) => format!("queued (position {})", position),
+            None => job.state.label().to_string(),
+        };
+        text.push_str(&format!("\n#{} {} - {}", job.id, state_text, job.url));
+    }
+    text
+}
+
+/// Cancels one job by ID, or all of the user's unfinished jobs without an ID.
+/// Admins may cancel jobs of other users.
+async fn cancel(state: &AppState, user_id: i64, args: &str) -> String {
+    let targets: Vec<_> = if args.is_empty() {
+        state
+            .jobs
+            .unfinished()
+            .into_iter()
+            .filter(|job| job.user_id == user_id)
+            .collect()
+    } else {
+        let Ok(job_id) = args.trim_start_matches('#').parse::<u64>() else {
+            return "Usage: /cancel [job id]".to_string();
+        };
+        match state.jobs.get(job_id) {
+            Some(job) if job.user_id == user_id || state.access.is_admin(user_id) => vec![job],
+            _ => return format!("Download #{} not found.", job_id),
+        }
+    };
+
+    let mut cancelled = Vec::new();
+    for job in targets {
+        match state.pool.cancel(job.id) {
+            Cancellation::Dequeued(job) => {
+                state.jobs.set_state(job.id, JobState::Cancelled);
+                if job.playlist.is_some() {
+                    playlist::item_finished(state, &job, JobState::Cancelled).await;
+                    cancelled.push(job.id);
+                    continue;
+                }
+                TelegramStatusMessage::resume(
+                    &state.telegram,
+                    job.chat_id,
+                    job.status_message_id,
+                    "Cancelled",
+                )
+                .await;
+                cancelled.push(job.id);
+            }
+            Cancellation::Signalled => cancelled.push(job.id),
+            Cancellation::NotFound => {
+                // Jobs waiting on the format keyboard are not in the pool yet
+                if let Some(job) = format_choice::apply_choice(state, job.id, Choice::Cancel) {
+                    TelegramStatusMessage::resume(
+                        &state.telegram,
+                        job.chat_id,
+                        job.status_message_id,
+                        "Cancelled",
+                    )
+                    .await;
+                    cancelled.push(job.id);
+                }
+            }
+        }
+    }
+
+    if cancelled.is_empty() {
+        return "Nothing to cancel.".to_string();
+    }
+    info!("User {} cancelled jobs {:?}", user_id, cancelled);
+    let ids: Vec<String> = cancelled.iter().map(|id| format!("#{}", id)).collect();
+    format!("Cancelled {}.", ids.join(", "))
+}
+
+fn admin_reply(state: &AppState, user_id: i64, command: Result<AdminCommand, String>) -> String {
+    if !state.access.is_admin(user_id) {
+        warn!("User {} tried to run an admin command", user_id);
+        "Only admins can change who may use this bot.".to_string()
+    } else {
+        match command {
+            Err(usage) => usage,
+            Ok(AdminCommand::Allow { id, role }) => match state.access.allow(id, role) {
+                Ok(AccessChange::AlreadyGranted) => format!("{} is already allowed.", id),
+                Ok(_) => {
+                    info!("User {} allowed {} as {:?}", user_id, id, role);
+                    format!("Allowed {} ({:?}).", id, role)
+                }
+                Err(e) => {
+                    error!("Failed to save access list: {}", e);
+                    "Failed to save the access list.".to_string()
+                }
+            },
+            Ok(AdminCommand::Deny { id }) => match state.access.deny(id) {
+                Ok(AccessChange::Revoked) => {
+                    info!("User {} denied {}", user_id, id);
+                    format!("Denied {}.", id)
+                }
+                Ok(AccessChange::Configured) => format!(
+                    "{} is configured in the environment and can only be removed there.",
+                    id
+                ),
+                Ok(_) => format!("{} was not on the allowlist.", id),
+                Err(e) => {
+                    error!("Failed to save access list: {}", e);
+                    "Failed to save the access list.".to_string()
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_command;
+    use crate::types::TelegramMessage;
+    use serde_json::json;
+
+    fn message(text: &str, command_length: usize) -> TelegramMessage {
+        serde_json::from_value(json!({
+            "chat": { "id": 1 },
+            "from": { "id": 1 },
+            "text": text,
+            "entities": [{ "type": "bot_command", "offset": 0, "length": command_length }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn command_and_arguments_are_split() {
+        let message = message("/cancel 12", 7);
+        let command = parse_command(&message).unwrap();
+
+        assert_eq!(command.name, "cancel");
+        assert_eq!(command.args, "12");
+    }
+
+    #[test]
+    fn bot_name_suffix_is_removed() {
+        let message = message("/Status@MyDownloaderBot", 23);
+
+        assert_eq!(parse_command(&message).unwrap().name, "status");
+    }
+
+    #[test]
+    fn plain_links_are_not_commands() {
+        let message: TelegramMessage = serde_json::from_value(json!({
+            "chat": { "id": 1 },
+            "from": { "id": 1 },
+            "text": "https://youtu.be/x",
+            "entities": [{ "type": "url", "offset": 0, "length": 18 }]
+        }))
+        .unwrap();
+
+        assert!(parse_command(&message).is_none());
+    }
+
+    #[test]
+    fn command_not_at_start_is_ignored() {
+        let message: TelegramMessage = serde_json::from_value(json!({
+            "chat": { "id": 1 },
+            "from": { "id": 1 },
+            "text": "see /help",
+            "entities": [{ "type": "bot_command", "offset": 4, "length": 5 }]
+        }))
+        .unwrap();
+
+        assert!(parse_command(&message).is_none());
+    }
+}
@@ -0,0 +1,192 @@
+use crate::access_control::StaticAccess;
+use crate::chunk_audio::{SplitConfig, SplitMode, UPLOAD_LIMIT};
+use crate::webhook;
+use dotenv::dotenv;
+use std::env;
+use std::path::PathBuf;
+
+const DEFAULT_DATA_DIR: &str = "./data";
+const DEFAULT_DOWNLOADS_DIR: &str = "./downloads";
+const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 2;
+const DEFAULT_TELEGRAM_API_URL: &str = "https://api.telegram.org";
+/// Upload limit of a self-hosted `telegram-bot-api` server.
+const LOCAL_SERVER_UPLOAD_LIMIT: u64 = 2000 * 1024 * 1024;
+
+/// How the service receives updates from Telegram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UpdateMode {
+    /// Telegram posts updates to the `/webhook` route; needs a public HTTPS endpoint.
+    Webhook,
+    /// The service long-polls `getUpdates`; works behind NAT.
+    Polling,
+}
+
+/// Service settings read from the environment (and `.env`) once at startup.
+pub(crate) struct Config {
+    pub(crate) bot_token: String,
+    /// Bot API server every request goes to, without a trailing slash.
+    pub(crate) telegram_api_url: String,
+    /// Pass files to the API server as `file://` paths instead of uploading
+    /// them; needs a server in `--local` mode on the same filesystem.
+    pub(crate) local_api: bool,
+    /// Files larger than this are split before sending.
+    pub(crate) upload_limit: u64,
+    /// Users and chats allowed through the environment, see `AccessList`.
+    pub(crate) access: StaticAccess,
+    pub(crate) force_ipv6: bool,
+    /// Directory holding state that must survive restarts, such as the job log.
+    pub(crate) data_dir: PathBuf,
+    /// Root of the per-job directories downloads are written to.
+    pub(crate) downloads_dir: PathBuf,
+    /// Upper bound on jobs processed at the same time; the rest wait in a queue.
+    pub(crate) max_concurrent_downloads: usize,
+    /// Secret Telegram must echo in `X-Telegram-Bot-Api-Secret-Token`.
+    pub(crate) webhook_secret: Option<String>,
+    /// Public URL registered with `setWebhook` on startup, if set.
+    pub(crate) webhook_url: Option<String>,
+    pub(crate) update_mode: UpdateMode,
+    /// Where oversized MP3 files are cut into parts.
+    pub(crate) split_mode: SplitMode,
+}
+
+impl Config {
+    pub(crate) fn from_env() -> Self {
+        dotenv().ok();
+
+        let bot_token = env::var("TELEGRAM_BOT_TOKEN").expect("TELEGRAM_BOT_TOKEN must be set");
+
+        // ALLOWED_USER_ID predates roles; that single user is treated as an admin.
+        let mut admin_user_ids = env_id_list("ADMIN_USER_IDS");
+        admin_user_ids.extend(env_id_list("ALLOWED_USER_ID"));
+        let access = StaticAccess {
+            admin_user_ids,
+            user_ids: env_id_list("ALLOWED_USER_IDS"),
+            chat_ids: env_id_list("ALLOWED_CHAT_IDS"),
+        };
+
+        // A local Bot API server accepts much larger files. A custom URL alone
+        // may just proxy api.telegram.org, which keeps the 50 MB limit.
+        let custom_api_url = env_non_empty("TELEGRAM_API_URL");
+        let local_api = env_bool_or_default("TELEGRAM_API_LOCAL", false);
+        assert!(
+            !local_api || custom_api_url.is_some(),
+            "TELEGRAM_API_LOCAL needs TELEGRAM_API_URL pointing at a local Bot API server"
+        );
+        let upload_limit = env::var("UPLOAD_LIMIT_MB")
+            .ok()
+            .map(|value| {
+                value
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&mb| mb > 0)
+                    .expect("UPLOAD_LIMIT_MB must be a positive integer")
+                    * 1024
+                    * 1024
+            })
+            .unwrap_or(if local_api {
+                LOCAL_SERVER_UPLOAD_LIMIT
+            } else {
+                UPLOAD_LIMIT
+            });
+
+        let webhook_secret = env_non_empty("WEBHOOK_SECRET");
+        if let Some(secret) = &webhook_secret {
+            assert!(
+                webhook::is_valid_secret(secret),
+                "WEBHOOK_SECRET must be 1-256 characters of A-Z, a-z, 0-9, _ or -"
+            );
+        }
+
+        Self {
+            bot_token,
+            telegram_api_url: custom_api_url
+                .map(|url| url.trim_end_matches('/').to_string())
+                .unwrap_or_else(|| DEFAULT_TELEGRAM_API_URL.to_string()),
+            local_api,
+            upload_limit,
+            access,
+            force_ipv6: env_bool_or_default("USE_IPV6", true),
+            data_dir: env::var("DATA_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| PathBuf::from(DEFAULT_DATA_DIR)),
+            downloads_dir: env_non_empty("DOWNLOADS_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_DOWNLOADS_DIR)),
+            max_concurrent_downloads: env::var("MAX_CONCURRENT_DOWNLOADS")
+                .ok()
+                .map(|value| {
+                    value
+                        .trim()
+                        .parse()
+                        .expect("MAX_CONCURRENT_DOWNLOADS must be a positive integer")
+                })
+                .filter(|&count| count > 0)
+                .unwrap_or(DEFAULT_MAX_CONCURRENT_DOWNLOADS),
+            webhook_secret,
+            webhook_url: env_non_empty("WEBHOOK_URL"),
+            update_mode: match env_non_empty("UPDATE_MODE")
+                .map(|mode| mode.to_ascii_lowercase())
+                .as_deref()
+            {
+                None | Some("webhook") => UpdateMode::Webhook,
+                Some("polling") => UpdateMode::Polling,
+                Some(other) => panic!(
+                    "UPDATE_MODE must be 'webhook' or 'polling', got '{}'",
+                    other
+                ),
+            },
+            split_mode: match env_non_empty("SPLIT_MODE")
+                .map(|mode| mode.to_ascii_lowercase())
+                .as_deref()
+            {
+                None | Some("size") => SplitMode::Size,
+                Some("silence") => SplitMode::Silence,
+                Some(other) => panic!("SPLIT_MODE must be 'size' or 'silence', got '{}'", other),
+            },
+        }
+    }
+
+    pub(crate) fn split_config(&self) -> SplitConfig {
+        SplitConfig {
+            upload_limit: self.upload_limit,
+            mode: self.split_mode,
+        }
+    }
+}
+
+/// Parses a comma-separated list of Telegram IDs.
+fn env_id_list(name: &str) -> Vec<i64> {
+    env::var(name)
+        .ok()
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(|id| {
+                    id.parse()
+                        .unwrap_or_else(|_| panic!("{} must contain valid integers", name))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn env_non_empty(name: &str) -> Option<String> {
+    env::var(name)
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn env_bool_or_default(name: &str, default: bool) -> bool {
+    env::var(name)
+        .ok()
+        .and_then(|value| match value.trim().to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" | "y" | "on" => Some(true),
+            "false" | "0" | "no" | "n" | "off" => Some(false),
+            _ => None,
+        })
+        .unwrap_or(default)
+}
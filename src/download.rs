@@ -0,0 +1,596 @@
+use crate::AppState;
+use crate::chapters;
+use crate::config::Config;
+use crate::file_cache;
+use crate::format_choice;
+use crate::id3::{self, Tags};
+use crate::in_flight::{Claim, Outcome};
+use crate::jobs::{Job, JobState};
+use crate::playlist;
+use crate::progress::{self, ProgressEvent};
+use crate::send_audio::{self, MediaInfo, SentFile, Upload, send_audio_to_telegram};
+use crate::telegram_status::TelegramStatusMessage;
+use crate::thumbnail;
+use crate::video_format;
+use crate::work_dir::WorkDir;
+use log::{debug, error, info, warn};
+use serde_json::Value;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio_util::sync::CancellationToken;
+
+/// A job interrupted by this many restarts is reported as failed instead of
+/// being retried again, so a URL that crashes the service cannot loop forever.
+const MAX_JOB_ATTEMPTS: u32 = 3;
+
+/// Picks up jobs left unfinished by a previous run of the service.
+pub(crate) fn resume_unfinished_jobs(state: &AppState) {
+    for job in state.jobs.unfinished() {
+        if job.state == JobState::AwaitingChoice {
+            // A keyboard that was shown before the restart still works;
+            // otherwise show it now.
+            if job.status_message_id.is_none() {
+                tokio::spawn(format_choice::offer_choices(state.clone(), job));
+            }
+            continue;
+        }
+
+        if job.attempts >= MAX_JOB_ATTEMPTS {
+            warn!(
+                "Job {} was interrupted {} times, giving up",
+                job.id, job.attempts
+            );
+            let state = state.clone();
+            tokio::spawn(async move {
+                state.jobs.set_state(job.id, JobState::Failed);
+                if job.playlist.is_some() {
+                    playlist::item_finished(&state, &job, JobState::Failed).await;
+                    return;
+                }
+                TelegramStatusMessage::resume(
+                    &state.telegram,
+                    job.chat_id,
+                    job.status_message_id,
+                    &format!("Download failed: interrupted by restarts\n{}", job.url),
+                )
+                .await;
+            });
+            continue;
+        }
+
+        info!("Resuming job {} ({:?}): {}", job.id, job.state, job.url);
+        state.pool.enqueue(job);
+    }
+}
+
+/// Runs a job to completion or until `cancel` is triggered. On cancellation
+/// the in-flight pipeline is dropped, which kills any running `yt-dlp`
+/// together with the `ffmpeg` it started.
+pub(crate) async fn run_job(state: AppState, job: Job, cancel: CancellationToken) {
+    let initial_text = if job.attempts == 0 {
+        "Starting..."
+    } else {
+        "Resuming after restart..."
+    };
+    // Playlist items report through the playlist's status message
+    let status = if job.playlist.is_some() {
+        TelegramStatusMessage::silent(&state.telegram, job.chat_id)
+    } else {
+        TelegramStatusMessage::resume(
+            &state.telegram,
+            job.chat_id,
+            job.status_message_id,
+            initial_text,
+        )
+        .await
+    };
+
+    state.jobs.update(job.id, |job| {
+        job.state = JobState::FetchingMetadata;
+        job.attempts += 1;
+        job.status_message_id = status.message_id();
+    });
+
+    let work_dir = WorkDir::create(&state.config.downloads_dir, job.id);
+    let succeeded = tokio::select! {
+        succeeded = async {
+            match &work_dir {
+                Ok(work_dir) => process_job(&state, &job, &status, work_dir.path()).await,
+                Err(e) => {
+                    error!("Failed to create work directory for job {}: {}", job.id, e);
+                    false
+                }
+            }
+        } => Some(succeeded),
+        _ = cancel.cancelled() => None,
+    };
+    // Removes the download and any parts left, whatever the outcome
+    drop(work_dir);
+
+    let final_state = match succeeded {
+        Some(true) => {
+            // Every file was confirmed by Telegram and has been deleted
+            state.jobs.set_state(job.id, JobState::Done);
+            status.delete().await;
+            JobState::Done
+        }
+        Some(false) => {
+            state.jobs.set_state(job.id, JobState::Failed);
+            status.update("Download failed").await;
+            JobState::Failed
+        }
+        None => {
+            info!("Job {} cancelled", job.id);
+            state.jobs.set_state(job.id, JobState::Cancelled);
+            status.update("Cancelled").await;
+            JobState::Cancelled
+        }
+    };
+    playlist::item_finished(&state, &job, final_state).await;
+}
+
+/// Runs `yt-dlp -j` for a single video; `None` if it fails or prints no JSON.
+pub(crate) async fn fetch_metadata(config: &Config, url: &str) -> Option<Value> {
+    let mut metadata_command = Command::new("yt-dlp");
+    metadata_command.kill_on_drop(true).arg("-j");
+    if config.force_ipv6 {
+        metadata_command.arg("-6");
+    }
+    let output = metadata_command
+        .arg("--no-playlist")
+        .arg(url)
+        .output()
+        .await;
+
+    output
+        .ok()
+        .and_then(|out| serde_json::from_slice(&out.stdout).ok())
+}
+
+/// Runs the job in `work_dir`, or waits for an identical one that is already
+/// running and forwards its files.
+async fn process_job(
+    state: &AppState,
+    job: &Job,
+    status: &TelegramStatusMessage,
+    work_dir: &Path,
+) -> bool {
+    let key = file_cache::request_key(&job.url, &job.options);
+    let leader = loop {
+        let follower = match state
+            .in_flight
+            .claim(&key, job.chat_id, job.playlist.is_some())
+        {
+            Claim::Leader(leader) => break Some(leader),
+            Claim::Follower(follower) => follower,
+            Claim::Independent => break None,
+        };
+
+        info!("Job {} waits for the running download of {}", job.id, key);
+        status
+            .update("Waiting for the same download to finish...")
+            .await;
+        let same_chat = follower.same_chat;
+        match follower.outcome().await {
+            // The chat gets the file from the other job
+            Outcome::Delivered(_) if same_chat => return true,
+            Outcome::Delivered(files) if !files.is_empty() => {
+                wait_for_upload_turn(state, job).await;
+                match send_audio::resend(&state.telegram, job.chat_id, &files).await {
+                    Ok(()) => return true,
+                    // A fresh download would send those parts again
+                    Err(delivered) if delivered > 0 => {
+                        warn!(
+                            "Job {} got {} of {} forwarded files, giving up",
+                            job.id,
+                            delivered,
+                            files.len()
+                        );
+                        return false;
+                    }
+                    Err(_) => {}
+                }
+            }
+            // Nothing to forward, so this job downloads the video itself
+            _ => {}
+        }
+    };
+
+    let sent = download_and_send(state, job, status, work_dir).await;
+    let delivered = sent.is_some();
+    if let Some(leader) = leader {
+        leader.finish(sent);
+    }
+    delivered
+}
+
+/// Downloads, converts and uploads the job's audio. Returns the stored files
+/// if everything was delivered.
+async fn download_and_send(
+    state: &AppState,
+    job: &Job,
+    status: &TelegramStatusMessage,
+    work_dir: &Path,
+) -> Option<Vec<SentFile>> {
+    let config = &state.config;
+
+    // Files sent before are forwarded by file_id instead of downloaded again
+    let url_key = file_cache::key_from_url(&job.url, &job.options);
+    if let Some(key) = &url_key {
+        match send_cached(state, job, key).await {
+            Cached::Sent(files) => return Some(files),
+            Cached::PartlySent => return None,
+            Cached::Missing => {}
+        }
+    }
+
+    // Step 1: get metadata
+    let metadata = fetch_metadata(config, &job.url).await;
+    let cache_key = metadata
+        .as_ref()
+        .and_then(|metadata| file_cache::key_from_metadata(metadata, &job.options));
+    if let Some(key) = &cache_key
+        && cache_key != url_key
+    {
+        match send_cached(state, job, key).await {
+            Cached::Sent(files) => return Some(files),
+            Cached::PartlySent => return None,
+            Cached::Missing => {}
+        }
+    }
+    let cache_key = cache_key.or(url_key);
+
+    let performer = metadata
+        .as_ref()
+        .and_then(|m| m.get("artist"))
+        .and_then(|a| a.as_str())
+        .unwrap_or("")
+        .to_string();
+
+    let title = metadata
+        .as_ref()
+        .and_then(|m| m.get("title"))
+        .and_then(|t| t.as_str())
+        .unwrap_or("Untitled")
+        .to_string();
+
+    // if artist is unknown, do not use it in the file name
+    let file_stem = if performer.is_empty() {
+        title.replace(['/', '\\'], "_")
+    } else {
+        format!("{} - {}", performer, title).replace(['/', '\\'], "_") // replace slashes and backslashes to avoid directory issues
+    };
+
+    // The extension is left to yt-dlp since `original` keeps the source codec;
+    // `%` is escaped because it starts a placeholder in the output template.
+    let output_template = format!(
+        "{}.%(ext)s",
+        work_dir
+            .join(&file_stem)
+            .display()
+            .to_string()
+            .replace('%', "%%")
+    );
+    let mut download_command = Command::new("yt-dlp");
+    // Its own process group, so a cancelled job can kill the postprocessors too
+    download_command.kill_on_drop(true).process_group(0);
+    if config.force_ipv6 {
+        download_command.arg("-6");
+    }
+    state.jobs.set_state(job.id, JobState::Downloading);
+    status.update("Downloading and converting...").await;
+    download_command.arg("--no-playlist").arg("-v");
+    match job.options.video_height {
+        Some(height) => {
+            let selector = match metadata.as_ref().and_then(|m| {
+                video_format::select_format(
+                    m,
+                    height,
+                    video_format::size_budget(config.upload_limit),
+                )
+            }) {
+                Some(selection) => {
+                    info!(
+                        "Job {} uses format {} ({}p, about {}MB)",
+                        job.id,
+                        selection.format_id,
+                        selection.height,
+                        selection.estimated_size / 1024 / 1024
+                    );
+                    selection.format_id
+                }
+                // Nothing is known to fit; the upload is split by time instead
+                None => video_format::fallback_selector(height),
+            };
+            download_command
+                .arg("-f")
+                .arg(selector)
+                .arg("--merge-output-format")
+                .arg("mp4")
+        }
+        None => download_command.args(job.options.yt_dlp_audio_args()),
+    };
+    let spawned = download_command
+        .args(progress::yt_dlp_args())
+        .arg("--print")
+        .arg("after_move:filepath") // report the final path on stdout
+        .arg("-o")
+        .arg(&output_template)
+        .arg(&job.url)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Failed to spawn yt-dlp for job {}: {}", file_stem, e);
+            return None;
+        }
+    };
+    let mut group = KillGroupOnDrop { pgid: child.id() };
+    let downloaded_file = follow_download(&mut child, status).await;
+    let exit = child.wait().await;
+    // Reaped, so the group ID may be reused from here on
+    group.pgid = None;
+    match exit {
+        Ok(exit) if exit.success() => {}
+        Ok(exit) => {
+            warn!("yt-dlp exited with status: {:?}", exit);
+            return None;
+        }
+        Err(e) => {
+            error!("Failed to wait for yt-dlp for job {}: {}", job.id, e);
+            return None;
+        }
+    }
+    let Some(downloaded_file) = downloaded_file else {
+        error!("yt-dlp did not report the output path for job {}", job.id);
+        return None;
+    };
+
+    let cover = match metadata.as_ref().and_then(thumbnail::thumbnail_url) {
+        Some(url) => thumbnail::fetch_jpeg(url).await,
+        None => None,
+    };
+    let tags = match &metadata {
+        Some(metadata)
+            if Path::new(&downloaded_file)
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("mp3")) =>
+        {
+            Some(Tags {
+                cover: cover.clone(),
+                ..Tags::from_metadata(metadata, &job.url)
+            })
+        }
+        _ => None,
+    };
+    let chapters = match &metadata {
+        Some(metadata) if job.options.chapters && job.options.video_height.is_none() => {
+            chapters::parse_chapters(metadata)
+        }
+        _ => Vec::new(),
+    };
+    let tracks = if chapters.is_empty() {
+        None
+    } else {
+        status.update("Splitting into chapters...").await;
+        chapters::split(&downloaded_file, &chapters, tags.as_ref()).await
+    };
+    if tracks.is_none()
+        && let Some(tags) = &tags
+    {
+        tag_mp3(&downloaded_file, tags).await;
+    }
+    let telegram_thumbnail = match &cover {
+        Some(cover) => thumbnail::telegram_thumbnail(cover).await,
+        None => None,
+    };
+
+    wait_for_upload_turn(state, job).await;
+    let upload = Upload {
+        telegram: &state.telegram,
+        chat_id: job.chat_id,
+        status,
+        label: "Uploading",
+    };
+    let caption = job.options.describe();
+    let media = MediaInfo {
+        performer: &performer,
+        title: &title,
+        caption: &caption,
+        thumbnail: telegram_thumbnail.as_deref(),
+        duration: None,
+    };
+    let sent = match tracks {
+        Some(tracks) => {
+            chapters::send_tracks(
+                &upload,
+                config,
+                &downloaded_file,
+                &tracks,
+                &chapters,
+                &media,
+            )
+            .await
+        }
+        None => send_audio_to_telegram(&upload, config, &downloaded_file, &media).await,
+    };
+    let files = sent?;
+    if let Some(key) = &cache_key
+        && !files.is_empty()
+        && let Err(e) = state.file_cache.insert(key, files.clone())
+    {
+        error!("Failed to cache files of {}: {}", key, e);
+    }
+    Some(files)
+}
+
+/// Kills a whole process group when dropped, such as `yt-dlp` with the
+/// `ffmpeg` its postprocessors run; `kill_on_drop` only reaches `yt-dlp`.
+struct KillGroupOnDrop {
+    /// Cleared once the group's leader has been waited for.
+    pgid: Option<u32>,
+}
+
+impl Drop for KillGroupOnDrop {
+    fn drop(&mut self) {
+        let Some(pgid) = self.pgid else {
+            return;
+        };
+        // SAFETY: killpg only sends a signal; it touches no memory of ours
+        if unsafe { libc::killpg(pgid as libc::pid_t, libc::SIGKILL) } == 0 {
+            info!("Killed yt-dlp process group {}", pgid);
+            return;
+        }
+        let e = std::io::Error::last_os_error();
+        // ESRCH: the group had already exited
+        if e.raw_os_error() != Some(libc::ESRCH) {
+            warn!("Failed to kill yt-dlp process group {}: {}", pgid, e);
+        }
+    }
+}
+
+/// Follows yt-dlp's output until it exits, showing its progress on the
+/// status message. Returns the last other line on stdout: the final path.
+async fn follow_download(child: &mut Child, status: &TelegramStatusMessage) -> Option<String> {
+    let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        return None;
+    };
+    let read_stdout = async {
+        let mut lines = BufReader::new(stdout).split(b'\n');
+        let mut last_line = None;
+        while let Ok(Some(line)) = lines.next_segment().await {
+            let line = String::from_utf8_lossy(&line);
+            if !report_progress(status, &line).await && !line.trim().is_empty() {
+                last_line = Some(line.trim().to_string());
+            }
+        }
+        last_line
+    };
+    // `--print` makes yt-dlp quiet, which moves the progress lines to stderr
+    let read_stderr = async {
+        let mut lines = BufReader::new(stderr).split(b'\n');
+        while let Ok(Some(line)) = lines.next_segment().await {
+            let line = String::from_utf8_lossy(&line);
+            if !report_progress(status, &line).await {
+                // yt-dlp's verbose log, shown with RUST_LOG=debug
+                debug!("yt-dlp: {}", line);
+            }
+        }
+    };
+    tokio::join!(read_stdout, read_stderr).0
+}
+
+/// Shows a line from yt-dlp's progress templates on the status message.
+/// Returns `false` for any other line.
+async fn report_progress(status: &TelegramStatusMessage, line: &str) -> bool {
+    match progress::parse_line(line) {
+        Some(ProgressEvent::Download(download)) => status.progress(&download.describe()).await,
+        Some(ProgressEvent::Postprocess(postprocessor)) => {
+            if let Some(text) = progress::describe_postprocessor(&postprocessor) {
+                status.update(text).await;
+            }
+        }
+        None => return false,
+    }
+    true
+}
+
+/// Playlist entries are delivered in order, so each waits for the ones before it.
+async fn wait_for_upload_turn(state: &AppState, job: &Job) {
+    if let Some(item) = job.playlist {
+        state
+            .playlists
+            .wait_for_turn(item.playlist_id, item.index)
+            .await;
+    }
+    state.jobs.set_state(job.id, JobState::Uploading);
+}
+
+/// What `send_cached` did with the files cached under a key.
+enum Cached {
+    /// Every file was delivered.
+    Sent(Vec<SentFile>),
+    /// Nothing usable was cached and nothing was sent, so the job downloads
+    /// the video.
+    Missing,
+    /// Some files arrived before Telegram refused one. Downloading again
+    /// would repeat them, so the job fails instead.
+    PartlySent,
+}
+
+/// Sends the files cached under `key`, if any. An entry Telegram no longer
+/// accepts is dropped.
+async fn send_cached(state: &AppState, job: &Job, key: &str) -> Cached {
+    let Some(files) = state.file_cache.get(key) else {
+        return Cached::Missing;
+    };
+
+    wait_for_upload_turn(state, job).await;
+    let delivered = match send_audio::resend(&state.telegram, job.chat_id, &files).await {
+        Ok(()) => {
+            info!("Job {} answered from cache ({})", job.id, key);
+            return Cached::Sent(files);
+        }
+        Err(delivered) => delivered,
+    };
+
+    if let Err(e) = state.file_cache.remove(key) {
+        error!("Failed to drop cached files of {}: {}", key, e);
+    }
+    if delivered > 0 {
+        warn!(
+            "Only {} of {} cached files of {} could be resent",
+            delivered,
+            files.len(),
+            key
+        );
+        Cached::PartlySent
+    } else {
+        warn!("Cached files of {} could not be resent, downloading", key);
+        Cached::Missing
+    }
+}
+
+/// Writes ID3 tags and the cover art. Failures are logged and
+/// the file is sent without them.
+async fn tag_mp3(path: &str, tags: &Tags) {
+    if let Err(e) = id3::write_tags(Path::new(path), tags).await {
+        warn!("Failed to write ID3 tags to {}: {}", path, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KillGroupOnDrop;
+    use std::process::Stdio;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::process::Command;
+
+    #[tokio::test]
+    async fn dropped_guard_kills_grandchildren() {
+        // The shell stands in for yt-dlp and its `sleep` for ffmpeg; both
+        // hold stdout open until they die
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("sleep 30 & wait")
+            .process_group(0)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+
+        drop(KillGroupOnDrop { pgid: child.id() });
+
+        assert!(!child.wait().await.unwrap().success());
+        let mut output = Vec::new();
+        let closed = tokio::time::timeout(Duration::from_secs(5), stdout.read_to_end(&mut output))
+            .await
+            .is_ok();
+        assert!(closed, "the grandchild must be killed with its parent");
+    }
+}
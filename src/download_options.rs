@@ -0,0 +1,267 @@
+use crate::audio_format::{AudioFormat, AudioQuality};
+use crate::video_format::DEFAULT_VIDEO_HEIGHT;
+use serde::{Deserialize, Serialize};
+
+/// Settings a job is downloaded with, resolved from the request and the
+/// user's preferences when the job is created.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DownloadOptions {
+    #[serde(default)]
+    pub(crate) format: AudioFormat,
+    #[serde(default)]
+    pub(crate) quality: AudioQuality,
+    /// Download the video up to this height instead of extracting audio.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) video_height: Option<u32>,
+    /// Send each chapter of the audio as its own track.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) chapters: bool,
+}
+
+impl DownloadOptions {
+    /// Short description for captions, e.g. `mp3, standard 192k`. The quality
+    /// is left out for `original`, which is never re-encoded.
+    pub(crate) fn describe(&self) -> String {
+        if let Some(height) = self.video_height {
+            return format!("video {}p", height);
+        }
+        match self.format {
+            AudioFormat::Original => self.format.name().to_string(),
+            format => format!("{}, {}", format.name(), self.quality.label()),
+        }
+    }
+
+    /// yt-dlp arguments extracting the audio. The quality preset is left out
+    /// for `original`, whose stream is copied: ffmpeg refuses to combine a
+    /// copied stream with filters such as the `-ac 1` of `voice`.
+    pub(crate) fn yt_dlp_audio_args(&self) -> Vec<&'static str> {
+        let mut args = vec!["-x", "--audio-format", self.format.yt_dlp_audio_format()];
+        if self.format != AudioFormat::Original {
+            args.extend(self.quality.yt_dlp_args());
+        }
+        args
+    }
+}
+
+/// A download request as typed by the user: a URL followed by optional
+/// option words, e.g. `https://youtu.be/abc opus` or `https://youtu.be/abc 480p`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct DownloadRequest {
+    pub(crate) url: String,
+    pub(crate) format: Option<AudioFormat>,
+    pub(crate) quality: Option<AudioQuality>,
+    pub(crate) video_height: Option<u32>,
+    /// Download every entry of a playlist link instead of a single video.
+    pub(crate) playlist: bool,
+    /// Split the audio at the video's chapters.
+    pub(crate) chapters: bool,
+}
+
+impl DownloadRequest {
+    /// Whether the user spelled out any options, in which case the format
+    /// keyboard is skipped.
+    pub(crate) fn has_options(&self) -> bool {
+        self.format.is_some() || self.quality.is_some() || self.video_height.is_some()
+    }
+
+    /// Fills in anything the request did not specify from `defaults`.
+    pub(crate) fn resolve(&self, defaults: DownloadOptions) -> DownloadOptions {
+        DownloadOptions {
+            format: self.format.unwrap_or(defaults.format),
+            quality: self.quality.unwrap_or(defaults.quality),
+            video_height: self.video_height,
+            chapters: self.chapters,
+        }
+    }
+}
+
+pub(crate) fn parse_request(text: &str) -> Result<DownloadRequest, String> {
+    let mut words = text.split_whitespace();
+    let Some(url) = words.next() else {
+        return Err("Send a link to download.".to_string());
+    };
+
+    let mut request = DownloadRequest {
+        url: url.to_string(),
+        format: None,
+        quality: None,
+        video_height: None,
+        playlist: false,
+        chapters: false,
+    };
+    for word in words {
+        if word.eq_ignore_ascii_case("playlist") {
+            request.playlist = true;
+        } else if word.eq_ignore_ascii_case("chapters") {
+            request.chapters = true;
+        } else if let Some(format) = AudioFormat::parse(word) {
+            request.format = Some(format);
+        } else if let Some(quality) = AudioQuality::parse(word) {
+            request.quality = Some(quality);
+        } else if let Some(height) = parse_video_height(word) {
+            request.video_height = Some(height);
+        } else {
+            return Err(format!(
+                "Unknown option '{}'. Formats: {}, video or a height like 480p. Qualities: {}. \
+                 Add playlist to download a whole playlist, or chapters to split at chapters.",
+                word,
+                format_names(),
+                quality_names()
+            ));
+        }
+    }
+    if request.chapters && request.video_height.is_some() {
+        return Err("Chapters can only be split from audio downloads.".to_string());
+    }
+    Ok(request)
+}
+
+/// `video` for the default height, or an explicit height such as `480p`.
+fn parse_video_height(word: &str) -> Option<u32> {
+    let word = word.to_ascii_lowercase();
+    if word == "video" {
+        return Some(DEFAULT_VIDEO_HEIGHT);
+    }
+    word.strip_suffix('p')?
+        .parse()
+        .ok()
+        .filter(|&height| height > 0)
+}
+
+pub(crate) fn format_names() -> String {
+    AudioFormat::ALL
+        .iter()
+        .map(|format| format.name())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub(crate) fn quality_names() -> String {
+    AudioQuality::ALL
+        .iter()
+        .map(|quality| quality.name())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DownloadOptions, parse_request};
+    use crate::audio_format::{AudioFormat, AudioQuality};
+
+    #[test]
+    fn bare_url_uses_defaults() {
+        let request = parse_request("https://youtu.be/abc").unwrap();
+        let defaults = DownloadOptions {
+            format: AudioFormat::Flac,
+            quality: AudioQuality::Best,
+            video_height: None,
+            chapters: false,
+        };
+
+        assert_eq!(request.url, "https://youtu.be/abc");
+        assert!(!request.has_options());
+        assert_eq!(request.resolve(defaults), defaults);
+    }
+
+    #[test]
+    fn original_is_extracted_without_the_quality_preset() {
+        let original = DownloadOptions {
+            format: AudioFormat::Original,
+            quality: AudioQuality::Voice,
+            ..DownloadOptions::default()
+        };
+        assert_eq!(
+            original.yt_dlp_audio_args(),
+            ["-x", "--audio-format", "best"]
+        );
+
+        let mp3 = DownloadOptions {
+            format: AudioFormat::Mp3,
+            ..original
+        };
+        assert_eq!(
+            mp3.yt_dlp_audio_args(),
+            [
+                "-x",
+                "--audio-format",
+                "mp3",
+                "--audio-quality",
+                "64K",
+                "--postprocessor-args",
+                "ExtractAudio:-ac 1"
+            ]
+        );
+    }
+
+    #[test]
+    fn format_word_overrides_default() {
+        let request = parse_request("https://youtu.be/abc  opus").unwrap();
+
+        assert_eq!(
+            request.resolve(DownloadOptions::default()).format,
+            AudioFormat::Opus
+        );
+    }
+
+    #[test]
+    fn format_and_quality_can_be_combined() {
+        let request = parse_request("https://youtu.be/abc voice m4a").unwrap();
+        let options = request.resolve(DownloadOptions::default());
+
+        assert_eq!(options.format, AudioFormat::M4a);
+        assert_eq!(options.quality, AudioQuality::Voice);
+        assert_eq!(options.describe(), "m4a, voice 64k mono");
+    }
+
+    #[test]
+    fn original_format_is_described_without_quality() {
+        let options = DownloadOptions {
+            format: AudioFormat::Original,
+            quality: AudioQuality::Voice,
+            video_height: None,
+            chapters: false,
+        };
+
+        assert_eq!(options.describe(), "original");
+    }
+
+    #[test]
+    fn video_words_select_video_mode() {
+        let defaults = DownloadOptions::default();
+
+        let request = parse_request("https://youtu.be/abc video").unwrap();
+        assert!(request.has_options());
+        assert_eq!(request.resolve(defaults).video_height, Some(720));
+
+        let request = parse_request("https://youtu.be/abc 480P").unwrap();
+        let options = request.resolve(defaults);
+        assert_eq!(options.video_height, Some(480));
+        assert_eq!(options.describe(), "video 480p");
+
+        assert!(parse_request("https://youtu.be/abc 0p").is_err());
+    }
+
+    #[test]
+    fn playlist_word_is_recognized() {
+        let request = parse_request("https://youtube.com/playlist?list=x Playlist opus").unwrap();
+
+        assert!(request.playlist);
+        assert_eq!(request.format, Some(AudioFormat::Opus));
+        assert!(!parse_request("https://youtu.be/abc").unwrap().playlist);
+    }
+
+    #[test]
+    fn chapters_word_applies_to_audio_only() {
+        let request = parse_request("https://youtu.be/abc chapters mp3").unwrap();
+        assert!(request.resolve(DownloadOptions::default()).chapters);
+
+        assert!(parse_request("https://youtu.be/abc chapters 480p").is_err());
+    }
+
+    #[test]
+    fn unknown_option_is_rejected() {
+        assert!(parse_request("https://youtu.be/abc wav").is_err());
+        assert!(parse_request("   ").is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use crate::audio_format::AudioFormat;
+use crate::download_options::DownloadOptions;
+use crate::send_audio::SentFile;
+use crate::storage;
+use reqwest::Url;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// yt-dlp's `extractor_key` for YouTube videos.
+const YOUTUBE_EXTRACTOR: &str = "Youtube";
+
+/// Files already delivered to Telegram, persisted as JSON keyed by video and
+/// download options, so repeat requests are answered by `file_id`.
+pub(crate) struct FileCache {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<String, Vec<SentFile>>>,
+}
+
+impl FileCache {
+    pub(crate) fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let entries = storage::load_json(&path)?;
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<Vec<SentFile>> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    pub(crate) fn insert(&self, key: &str, files: Vec<SentFile>) -> io::Result<()> {
+        self.change(|entries| {
+            entries.insert(key.to_string(), files);
+        })
+    }
+
+    /// Drops an entry whose files Telegram no longer accepts.
+    pub(crate) fn remove(&self, key: &str) -> io::Result<()> {
+        self.change(|entries| {
+            entries.remove(key);
+        })
+    }
+
+    fn change(&self, change: impl FnOnce(&mut BTreeMap<String, Vec<SentFile>>)) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        let mut updated = entries.clone();
+        change(&mut updated);
+        storage::save_json(&self.path, &updated)?;
+        *entries = updated;
+        Ok(())
+    }
+}
+
+/// Cache key such as `Youtube/dQw4w9WgXcQ/mp3-standard`. The quality is left
+/// out for `original`, which is downloaded without the quality preset.
+pub(crate) fn cache_key(extractor: &str, video_id: &str, options: &DownloadOptions) -> String {
+    let variant = match (options.video_height, options.format) {
+        (Some(height), _) => format!("{}p", height),
+        (None, AudioFormat::Original) => options.format.name().to_string(),
+        (None, format) => format!("{}-{}", format.name(), options.quality.name()),
+    };
+    let chapters = if options.chapters { "-chapters" } else { "" };
+    format!("{}/{}/{}{}", extractor, video_id, variant, chapters)
+}
+
+/// Key from the `extractor_key` and `id` of `yt-dlp -j` metadata.
+pub(crate) fn key_from_metadata(metadata: &Value, options: &DownloadOptions) -> Option<String> {
+    let extractor = metadata.get("extractor_key")?.as_str()?;
+    let video_id = metadata.get("id")?.as_str()?;
+    Some(cache_key(extractor, video_id, options))
+}
+
+/// Key for YouTube links, known without asking yt-dlp, so cached videos are
+/// answered without any network request.
+pub(crate) fn key_from_url(url: &str, options: &DownloadOptions) -> Option<String> {
+    let video_id = youtube_video_id(url)?;
+    Some(cache_key(YOUTUBE_EXTRACTOR, &video_id, options))
+}
+
+/// Identifies a request before its metadata is known: by video for YouTube
+/// links, otherwise by the link itself.
+pub(crate) fn request_key(url: &str, options: &DownloadOptions) -> String {
+    key_from_url(url, options).unwrap_or_else(|| cache_key("url", url.trim(), options))
+}
+
+fn youtube_video_id(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    let mut segments = url.path_segments()?;
+    let video_id = match host {
+        "youtu.be" => segments.next()?.to_string(),
+        "youtube.com" | "www.youtube.com" | "m.youtube.com" | "music.youtube.com" => {
+            match segments.next()? {
+                "watch" => url
+                    .query_pairs()
+                    .find(|(name, _)| name == "v")
+                    .map(|(_, value)| value.into_owned())?,
+                "shorts" | "live" | "embed" => segments.next()?.to_string(),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    is_youtube_id(&video_id).then_some(video_id)
+}
+
+fn is_youtube_id(id: &str) -> bool {
+    id.len() == 11
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileCache, cache_key, key_from_metadata, key_from_url, request_key};
+    use crate::audio_format::{AudioFormat, AudioQuality};
+    use crate::download_options::DownloadOptions;
+    use crate::send_audio::{SendMethod, SentFile};
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn sent(file_id: &str) -> SentFile {
+        SentFile {
+            method: SendMethod::Audio,
+            file_id: file_id.to_string(),
+            caption: "mp3, standard 192k".to_string(),
+        }
+    }
+
+    #[test]
+    fn key_covers_format_quality_and_chapters() {
+        let options = DownloadOptions::default();
+        assert_eq!(
+            cache_key("Youtube", "abc", &options),
+            "Youtube/abc/mp3-standard"
+        );
+
+        let original = DownloadOptions {
+            format: AudioFormat::Original,
+            quality: AudioQuality::Voice,
+            ..options
+        };
+        assert_eq!(
+            cache_key("Youtube", "abc", &original),
+            "Youtube/abc/original"
+        );
+
+        let video = DownloadOptions {
+            video_height: Some(480),
+            ..options
+        };
+        assert_eq!(cache_key("Youtube", "abc", &video), "Youtube/abc/480p");
+
+        let chapters = DownloadOptions {
+            chapters: true,
+            ..options
+        };
+        assert_eq!(
+            cache_key("Youtube", "abc", &chapters),
+            "Youtube/abc/mp3-standard-chapters"
+        );
+    }
+
+    #[test]
+    fn youtube_links_match_metadata_keys() {
+        let options = DownloadOptions::default();
+        let metadata = json!({ "extractor_key": "Youtube", "id": "dQw4w9WgXcQ" });
+        let expected = key_from_metadata(&metadata, &options);
+
+        for url in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42",
+            "https://youtu.be/dQw4w9WgXcQ?si=share",
+            "https://m.youtube.com/shorts/dQw4w9WgXcQ",
+            "https://music.youtube.com/watch?list=RD&v=dQw4w9WgXcQ",
+        ] {
+            assert_eq!(key_from_url(url, &options), expected, "{}", url);
+        }
+
+        assert_eq!(
+            key_from_url("https://youtube.com/playlist?list=PL1", &options),
+            None
+        );
+        assert_eq!(key_from_url("https://youtu.be/short", &options), None);
+        assert_eq!(key_from_url("https://vimeo.com/12345", &options), None);
+    }
+
+    #[test]
+    fn other_links_are_keyed_by_url() {
+        let options = DownloadOptions::default();
+
+        assert_eq!(
+            request_key("https://vimeo.com/12345", &options),
+            "url/https://vimeo.com/12345/mp3-standard"
+        );
+        assert_eq!(
+            request_key("https://youtu.be/dQw4w9WgXcQ", &options),
+            "Youtube/dQw4w9WgXcQ/mp3-standard"
+        );
+    }
+
+    #[test]
+    fn entries_survive_reopening() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("file_cache.json");
+
+        let cache = FileCache::open(&path).unwrap();
+        cache
+            .insert("Youtube/a/mp3-standard", vec![sent("one"), sent("two")])
+            .unwrap();
+        cache
+            .insert("Youtube/b/mp3-standard", vec![sent("three")])
+            .unwrap();
+        cache.remove("Youtube/b/mp3-standard").unwrap();
+
+        let cache = FileCache::open(&path).unwrap();
+        assert_eq!(
+            cache.get("Youtube/a/mp3-standard"),
+            Some(vec![sent("one"), sent("two")])
+        );
+        assert_eq!(cache.get("Youtube/b/mp3-standard"), None);
+    }
+}
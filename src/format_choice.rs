@@ -0,0 +1,343 @@
+use crate::AppState;
+use crate::audio_format::AudioFormat;
+use crate::download;
+use crate::jobs::{Job, JobState};
+use crate::telegram::{AnswerCallbackQuery, TelegramClient};
+use crate::telegram_status::TelegramStatusMessage;
+use crate::types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup};
+use crate::worker_pool;
+use log::{error, info, warn};
+use serde_json::Value;
+
+/// Video heights offered on the keyboard, tallest first. The smallest one is
+/// offered for any video, since the selector falls back to smaller streams.
+const VIDEO_HEIGHTS: [u32; 2] = [720, 360];
+
+/// A button of the format keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Choice {
+    Audio(AudioFormat),
+    Video(u32),
+    Cancel,
+}
+
+impl Choice {
+    fn code(self) -> String {
+        match self {
+            Choice::Audio(format) => format.name().to_string(),
+            Choice::Video(height) => format!("{}p", height),
+            Choice::Cancel => "cancel".to_string(),
+        }
+    }
+
+    fn parse_code(code: &str) -> Option<Self> {
+        if code == "cancel" {
+            return Some(Choice::Cancel);
+        }
+        if let Some(height) = code.strip_suffix('p')
+            && let Ok(height) = height.parse()
+        {
+            return Some(Choice::Video(height));
+        }
+        AudioFormat::parse(code).map(Choice::Audio)
+    }
+
+    fn label(self) -> String {
+        match self {
+            Choice::Audio(AudioFormat::Opus) => "Audio Opus".to_string(),
+            Choice::Audio(AudioFormat::Original) => "Audio original".to_string(),
+            Choice::Audio(format) => format!("Audio {}", format.name().to_ascii_uppercase()),
+            Choice::Video(height) => format!("Video {}p", height),
+            Choice::Cancel => "Cancel".to_string(),
+        }
+    }
+}
+
+/// Callback data for a button, e.g. `12:opus`. Telegram allows 64 bytes.
+fn callback_data(job_id: u64, choice: Choice) -> String {
+    format!("{}:{}", job_id, choice.code())
+}
+
+pub(crate) fn parse_callback_data(data: &str) -> Option<(u64, Choice)> {
+    let (job_id, code) = data.split_once(':')?;
+    Some((job_id.parse().ok()?, Choice::parse_code(code)?))
+}
+
+/// Choices offered for a video: the user's default audio format, MP3 and
+/// Opus, then the video heights the source has streams for.
+pub(crate) fn choices_for(metadata: Option<&Value>, default_format: AudioFormat) -> Vec<Choice> {
+    let mut choices = vec![Choice::Audio(default_format)];
+    for format in [AudioFormat::Mp3, AudioFormat::Opus] {
+        if format != default_format {
+            choices.push(Choice::Audio(format));
+        }
+    }
+
+    let Some(max_height) = metadata.and_then(max_video_height) else {
+        return choices;
+    };
+    let smallest = VIDEO_HEIGHTS[VIDEO_HEIGHTS.len() - 1];
+    choices.extend(
+        VIDEO_HEIGHTS
+            .into_iter()
+            .filter(|&height| height <= max_height || height == smallest)
+            .map(Choice::Video),
+    );
+    choices
+}
+
+/// Tallest video stream in `yt-dlp -j` output, ignoring audio-only formats.
+fn max_video_height(metadata: &Value) -> Option<u32> {
+    let formats = metadata.get("formats").and_then(Value::as_array);
+    formats
+        .into_iter()
+        .flatten()
+        .chain(std::iter::once(metadata))
+        .filter(|format| format.get("vcodec").and_then(Value::as_str) != Some("none"))
+        .filter_map(|format| format.get("height").and_then(Value::as_u64))
+        .max()
+        .map(|height| height.min(u64::from(u32::MAX)) as u32)
+}
+
+/// Audio buttons on the first row, video on the second, then Cancel.
+fn keyboard(job_id: u64, choices: &[Choice]) -> InlineKeyboardMarkup {
+    let button = |choice: &Choice| InlineKeyboardButton {
+        text: choice.label(),
+        callback_data: callback_data(job_id, *choice),
+    };
+    let audio = choices
+        .iter()
+        .filter(|choice| matches!(choice, Choice::Audio(_)))
+        .map(button)
+        .collect();
+    let video: Vec<_> = choices
+        .iter()
+        .filter(|choice| matches!(choice, Choice::Video(_)))
+        .map(button)
+        .collect();
+
+    let mut rows = vec![audio];
+    if !video.is_empty() {
+        rows.push(video);
+    }
+    rows.push(vec![button(&Choice::Cancel)]);
+    InlineKeyboardMarkup {
+        inline_keyboard: rows,
+    }
+}
+
+/// Shows the format keyboard for a job in `AwaitingChoice`. The job is left
+/// waiting until a button is tapped.
+pub(crate) async fn offer_choices(state: AppState, job: Job) {
+    let status = TelegramStatusMessage::resume(
+        &state.telegram,
+        job.chat_id,
+        job.status_message_id,
+        "Looking up available formats...",
+    )
+    .await;
+    if status.message_id().is_none() {
+        // Without a message there is nothing to tap, so the job would wait forever.
+        error!("Could not show format choices for job {}", job.id);
+        state.jobs.set_state(job.id, JobState::Failed);
+        return;
+    }
+    state
+        .jobs
+        .update(job.id, |job| job.status_message_id = status.message_id());
+
+    let metadata = download::fetch_metadata(&state.config, &job.url).await;
+    let title = metadata
+        .as_ref()
+        .and_then(|m| m.get("title"))
+        .and_then(Value::as_str)
+        .unwrap_or(&job.url);
+    let choices = choices_for(metadata.as_ref(), job.options.format);
+
+    status
+        .update_with_keyboard(
+            &format!("{}\nChoose a format:", title),
+            &keyboard(job.id, &choices),
+        )
+        .await;
+}
+
+/// Applies `choice` if the job is still waiting for one. Returns the updated
+/// job, or `None` if it was already started or cancelled.
+pub(crate) fn apply_choice(state: &AppState, job_id: u64, choice: Choice) -> Option<Job> {
+    let mut accepted = false;
+    let job = state.jobs.update(job_id, |job| {
+        if job.state != JobState::AwaitingChoice {
+            return;
+        }
+        accepted = true;
+        match choice {
+            Choice::Audio(format) => {
+                job.options.format = format;
+                job.options.video_height = None;
+                job.state = JobState::Queued;
+            }
+            Choice::Video(height) => {
+                job.options.video_height = Some(height);
+                job.state = JobState::Queued;
+            }
+            Choice::Cancel => job.state = JobState::Cancelled,
+        }
+    })?;
+    accepted.then_some(job)
+}
+
+/// Handles a tap on the format keyboard. Access is checked by the caller.
+pub(crate) async fn handle_callback(state: &AppState, query: CallbackQuery) {
+    let user_id = query.from.id;
+    let mut chosen = None;
+    let reply = match query.data.as_deref().and_then(parse_callback_data) {
+        None => Some("Unknown button."),
+        Some((job_id, choice)) => match state.jobs.get(job_id) {
+            Some(job) if job.user_id == user_id || state.access.is_admin(user_id) => {
+                chosen = apply_choice(state, job_id, choice);
+                match chosen {
+                    Some(_) => None,
+                    None => Some("This download was already started or cancelled."),
+                }
+            }
+            _ => Some("This download belongs to someone else."),
+        },
+    };
+
+    // Answer first so the button stops spinning while the job is queued.
+    answer(&state.telegram, &query.id, reply).await;
+
+    let Some(job) = chosen else {
+        return;
+    };
+    if job.state == JobState::Cancelled {
+        info!(
+            "User {} cancelled job {} from the keyboard",
+            user_id, job.id
+        );
+        TelegramStatusMessage::resume(
+            &state.telegram,
+            job.chat_id,
+            job.status_message_id,
+            "Cancelled",
+        )
+        .await;
+        return;
+    }
+
+    info!("Job {} will download {}", job.id, job.options.describe());
+    // Editing without a keyboard removes the buttons.
+    TelegramStatusMessage::resume(
+        &state.telegram,
+        job.chat_id,
+        job.status_message_id,
+        &worker_pool::queued_text(state.pool.queued_len() + 1),
+    )
+    .await;
+    state.pool.enqueue(job);
+}
+
+/// Acknowledges a button tap, optionally showing `text` as a notification.
+pub(crate) async fn answer(telegram: &TelegramClient, callback_query_id: &str, text: Option<&str>) {
+    let request = AnswerCallbackQuery {
+        callback_query_id,
+        text,
+    };
+    if let Err(e) = telegram.answer_callback_query(&request).await {
+        warn!(
+            "Failed to answer callback query {}: {}",
+            callback_query_id, e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Choice, choices_for, keyboard, parse_callback_data};
+    use crate::audio_format::AudioFormat;
+    use serde_json::json;
+
+    #[test]
+    fn callback_data_round_trips() {
+        for choice in [
+            Choice::Audio(AudioFormat::Opus),
+            Choice::Video(720),
+            Choice::Cancel,
+        ] {
+            let data = super::callback_data(12, choice);
+            assert_eq!(parse_callback_data(&data), Some((12, choice)));
+        }
+        assert_eq!(parse_callback_data("12:wav"), None);
+        assert_eq!(parse_callback_data("x:mp3"), None);
+    }
+
+    #[test]
+    fn video_heights_follow_available_streams() {
+        let metadata = json!({
+            "formats": [
+                { "vcodec": "none", "height": null },
+                { "vcodec": "avc1", "height": 480 },
+                { "vcodec": "vp9", "height": 1080 }
+            ]
+        });
+
+        let choices = choices_for(Some(&metadata), AudioFormat::Flac);
+
+        assert_eq!(
+            choices,
+            vec![
+                Choice::Audio(AudioFormat::Flac),
+                Choice::Audio(AudioFormat::Mp3),
+                Choice::Audio(AudioFormat::Opus),
+                Choice::Video(720),
+                Choice::Video(360),
+            ]
+        );
+    }
+
+    #[test]
+    fn low_resolution_video_offers_smallest_height() {
+        let metadata = json!({ "formats": [{ "vcodec": "avc1", "height": 240 }] });
+
+        let choices = choices_for(Some(&metadata), AudioFormat::Mp3);
+
+        assert_eq!(
+            choices,
+            vec![
+                Choice::Audio(AudioFormat::Mp3),
+                Choice::Audio(AudioFormat::Opus),
+                Choice::Video(360),
+            ]
+        );
+    }
+
+    #[test]
+    fn audio_only_sources_get_no_video_buttons() {
+        let metadata = json!({ "formats": [{ "vcodec": "none" }] });
+
+        assert!(
+            choices_for(Some(&metadata), AudioFormat::Mp3)
+                .iter()
+                .all(|choice| matches!(choice, Choice::Audio(_)))
+        );
+        assert_eq!(choices_for(None, AudioFormat::Mp3).len(), 2);
+    }
+
+    #[test]
+    fn keyboard_ends_with_cancel_row() {
+        let markup = keyboard(7, &[Choice::Audio(AudioFormat::Mp3), Choice::Video(720)]);
+        let json = serde_json::to_value(&markup).unwrap();
+
+        assert_eq!(
+            json,
+            json!({
+                "inline_keyboard": [
+                    [{ "text": "Audio MP3", "callback_data": "7:mp3" }],
+                    [{ "text": "Video 720p", "callback_data": "7:720p" }],
+                    [{ "text": "Cancel", "callback_data": "7:cancel" }]
+                ]
+            })
+        );
+    }
+}
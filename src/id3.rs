@@ -0,0 +1,257 @@
+use crate::chunk_audio::id3v2_tag_len;
+use serde_json::Value;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// Metadata written into an MP3 as an ID3v2.3 tag.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Tags {
+    pub(crate) title: Option<String>,
+    pub(crate) artist: Option<String>,
+    pub(crate) album: Option<String>,
+    pub(crate) year: Option<String>,
+    pub(crate) track: Option<u64>,
+    /// Stored in a comment frame; the source URL.
+    pub(crate) comment: Option<String>,
+    /// JPEG cover art, stored as the front cover picture.
+    pub(crate) cover: Option<Vec<u8>>,
+}
+
+impl Tags {
+    /// Reads tags from `yt-dlp -j` output. Music metadata is preferred and
+    /// falls back to the generic video fields.
+    pub(crate) fn from_metadata(metadata: &Value, url: &str) -> Self {
+        let text = |keys: &[&str]| {
+            keys.iter()
+                .filter_map(|key| metadata.get(*key).and_then(Value::as_str))
+                .find(|value| !value.is_empty())
+                .map(String::from)
+        };
+        let year = metadata
+            .get("release_year")
+            .and_then(Value::as_u64)
+            .map(|year| year.to_string())
+            .or_else(|| text(&["upload_date"]).and_then(|date| date.get(..4).map(String::from)));
+
+        Tags {
+            title: text(&["track", "title"]),
+            artist: text(&["artist", "creator", "uploader"]),
+            album: text(&["album"]),
+            year,
+            track: metadata.get("track_number").and_then(Value::as_u64),
+            comment: Some(text(&["webpage_url"]).unwrap_or_else(|| url.to_string())),
+            cover: None,
+        }
+    }
+}
+
+/// Encodes `tags` as a complete ID3v2.3 tag. Text is stored as UTF-16 so
+/// non-Latin titles survive in every player.
+pub(crate) fn encode_tag(tags: &Tags) -> Vec<u8> {
+    let mut frames = Vec::new();
+    let text_frames = [
+        (b"TIT2", tags.title.clone()),
+        (b"TPE1", tags.artist.clone()),
+        (b"TALB", tags.album.clone()),
+        (b"TYER", tags.year.clone()),
+        (b"TRCK", tags.track.map(|track| track.to_string())),
+    ];
+    for (id, value) in text_frames {
+        if let Some(value) = value {
+            let mut body = vec![1]; // UTF-16 with BOM
+            body.extend(utf16_with_bom(&value));
+            push_frame(&mut frames, id, &body);
+        }
+    }
+
+    if let Some(comment) = &tags.comment {
+        let mut body = vec![1];
+        body.extend(b"eng");
+        body.extend(utf16_with_bom("")); // empty description
+        body.extend([0, 0]);
+        body.extend(utf16_with_bom(comment));
+        push_frame(&mut frames, b"COMM", &body);
+    }
+
+    if let Some(cover) = &tags.cover {
+        let mut body = vec![0]; // ISO-8859-1 for the MIME type and description
+        body.extend(b"image/jpeg\0");
+        body.push(3); // front cover
+        body.push(0); // empty description
+        body.extend(cover);
+        push_frame(&mut frames, b"APIC", &body);
+    }
+
+    let mut tag = Vec::with_capacity(10 + frames.len());
+    tag.extend(b"ID3");
+    tag.extend([3, 0, 0]); // version 2.3.0, no flags
+    tag.extend(syncsafe(frames.len() as u32));
+    tag.extend(frames);
+    tag
+}
+
+fn push_frame(frames: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    frames.extend(id);
+    // ID3v2.3 frame sizes are plain big-endian, unlike the tag size.
+    frames.extend((body.len() as u32).to_be_bytes());
+    frames.extend([0, 0]);
+    frames.extend(body);
+}
+
+fn utf16_with_bom(text: &str) -> Vec<u8> {
+    let mut bytes = vec![0xFF, 0xFE];
+    for unit in text.encode_utf16() {
+        bytes.extend(unit.to_le_bytes());
+    }
+    bytes
+}
+
+/// Encodes a 28-bit size as four 7-bit bytes, as the tag header requires.
+fn syncsafe(size: u32) -> [u8; 4] {
+    [
+        (size >> 21 & 0x7F) as u8,
+        (size >> 14 & 0x7F) as u8,
+        (size >> 7 & 0x7F) as u8,
+        (size & 0x7F) as u8,
+    ]
+}
+
+/// Replaces any ID3v2 tag at the start of the MP3 at `path` with `tags`.
+/// The audio is streamed into a temporary file that is renamed over the
+/// original, so a failure leaves the file untouched.
+pub(crate) async fn write_tags(path: &Path, tags: &Tags) -> io::Result<()> {
+    let mut source = File::open(path).await?;
+    let mut header = [0u8; 10];
+    let audio_start = match source.read_exact(&mut header).await {
+        Ok(_) => id3v2_tag_len(&header),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+        Err(e) => return Err(e),
+    };
+    source.seek(io::SeekFrom::Start(audio_start)).await?;
+
+    let temp_path = PathBuf::from(format!("{}.tagging", path.display()));
+    let result = async {
+        let mut target = File::create(&temp_path).await?;
+        target.write_all(&encode_tag(tags)).await?;
+        tokio::io::copy(&mut source, &mut target).await?;
+        target.flush().await?;
+        fs::rename(&temp_path, path).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path).await;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Tags, encode_tag, id3v2_tag_len, syncsafe, write_tags};
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[test]
+    fn syncsafe_sizes_use_seven_bits_per_byte() {
+        assert_eq!(syncsafe(0x7F), [0, 0, 0, 0x7F]);
+        assert_eq!(syncsafe(0x80), [0, 0, 1, 0]);
+        assert_eq!(syncsafe(257_000), [0, 0x0F, 0x57, 0x68]);
+    }
+
+    #[test]
+    fn title_frame_is_utf16_with_bom() {
+        let tag = encode_tag(&Tags {
+            title: Some("Hé".to_string()),
+            ..Tags::default()
+        });
+
+        assert_eq!(&tag[..6], b"ID3\x03\x00\x00");
+        assert_eq!(&tag[6..10], &syncsafe(17));
+        assert_eq!(&tag[10..14], b"TIT2");
+        assert_eq!(&tag[14..18], &7u32.to_be_bytes());
+        assert_eq!(&tag[20..], &[1, 0xFF, 0xFE, b'H', 0, 0xE9, 0]);
+        assert_eq!(id3v2_tag_len(tag[..10].try_into().unwrap()), 27);
+    }
+
+    #[test]
+    fn comment_and_cover_frames_are_encoded() {
+        let tag = encode_tag(&Tags {
+            comment: Some("u".to_string()),
+            cover: Some(vec![0xFF, 0xD8]),
+            ..Tags::default()
+        });
+
+        let comm = &tag[10..];
+        assert_eq!(&comm[..4], b"COMM");
+        assert_eq!(
+            &comm[10..22],
+            &[1, b'e', b'n', b'g', 0xFF, 0xFE, 0, 0, 0xFF, 0xFE, b'u', 0]
+        );
+        let apic = &comm[22..];
+        assert_eq!(&apic[..4], b"APIC");
+        assert_eq!(&apic[10..], b"\0image/jpeg\0\x03\0\xFF\xD8");
+    }
+
+    #[test]
+    fn tags_come_from_metadata() {
+        let metadata = json!({
+            "title": "Song (Official Video)",
+            "track": "Song",
+            "uploader": "Channel",
+            "album": "Album",
+            "upload_date": "20190412",
+            "track_number": 3,
+            "webpage_url": "https://www.youtube.com/watch?v=x"
+        });
+
+        let tags = Tags::from_metadata(&metadata, "https://youtu.be/x");
+
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.artist.as_deref(), Some("Channel"));
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+        assert_eq!(tags.year.as_deref(), Some("2019"));
+        assert_eq!(tags.track, Some(3));
+        assert_eq!(
+            tags.comment.as_deref(),
+            Some("https://www.youtube.com/watch?v=x")
+        );
+    }
+
+    #[tokio::test]
+    async fn existing_tag_is_replaced_and_audio_kept() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("song.mp3");
+        let old_tag = encode_tag(&Tags {
+            title: Some("Old title that is rather long".to_string()),
+            ..Tags::default()
+        });
+        let audio = [0xFF, 0xFB, 0x90, 0x00, 1, 2, 3];
+        std::fs::write(&path, [old_tag.as_slice(), &audio].concat()).unwrap();
+
+        let tags = Tags {
+            title: Some("New".to_string()),
+            ..Tags::default()
+        };
+        write_tags(&path, &tags).await.unwrap();
+
+        let contents = std::fs::read(&path).unwrap();
+        let new_tag = encode_tag(&tags);
+        assert_eq!(&contents[..new_tag.len()], new_tag.as_slice());
+        assert_eq!(&contents[new_tag.len()..], &audio);
+    }
+
+    #[tokio::test]
+    async fn untagged_file_gets_a_tag_prepended() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("song.mp3");
+        std::fs::write(&path, [0xFF, 0xFB]).unwrap();
+
+        write_tags(&path, &Tags::default()).await.unwrap();
+
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            b"ID3\x03\0\0\0\0\0\0\xFF\xFB"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use crate::send_audio::SentFile;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// How a download that other jobs are waiting on ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Running,
+    /// The stored files, empty if Telegram did not name all of them.
+    Delivered(Vec<SentFile>),
+    /// Failed or cancelled.
+    Failed,
+}
+
+struct Entry {
+    /// Chats that already get the file from this download.
+    chats: HashSet<i64>,
+    /// The leader is a playlist item, which uploads only in its turn.
+    in_playlist: bool,
+    outcome: watch::Receiver<Outcome>,
+}
+
+/// Downloads currently running, keyed like the file cache, so an identical
+/// request waits for the running one instead of downloading the same video.
+#[derive(Default)]
+pub(crate) struct InFlight {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+pub(crate) enum Claim {
+    /// Nobody is downloading `key`; the caller does and reports the outcome.
+    Leader(Leader),
+    Follower(Follower),
+    /// A playlist item is downloading `key` and the caller is one too; the
+    /// caller downloads on its own. The leader may be waiting for the
+    /// caller's turn, so waiting for the leader could wait forever.
+    Independent,
+}
+
+/// Held by the job doing the download. Dropping it without `finish`, e.g. on
+/// cancellation, reports a failure to the followers.
+pub(crate) struct Leader {
+    in_flight: Arc<InFlight>,
+    key: String,
+    outcome: watch::Sender<Outcome>,
+}
+
+pub(crate) struct Follower {
+    outcome: watch::Receiver<Outcome>,
+    /// The chat already gets the file from the leader.
+    pub(crate) same_chat: bool,
+}
+
+impl InFlight {
+    /// `in_playlist` tells whether the caller is a playlist item.
+    pub(crate) fn claim(self: &Arc<Self>, key: &str, chat_id: i64, in_playlist: bool) -> Claim {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get_mut(key) {
+            if entry.in_playlist && in_playlist {
+                return Claim::Independent;
+            }
+            return Claim::Follower(Follower {
+                outcome: entry.outcome.clone(),
+                same_chat: !entry.chats.insert(chat_id),
+            });
+        }
+
+        let (sender, receiver) = watch::channel(Outcome::Running);
+        entries.insert(
+            key.to_string(),
+            Entry {
+                chats: HashSet::from([chat_id]),
+                in_playlist,
+                outcome: receiver,
+            },
+        );
+        Claim::Leader(Leader {
+            in_flight: Arc::clone(self),
+            key: key.to_string(),
+            outcome: sender,
+        })
+    }
+}
+
+impl Leader {
+    /// Hands the result to the followers: the stored files, or `None` if the
+    /// download failed.
+    pub(crate) fn finish(self, files: Option<Vec<SentFile>>) {
+        let outcome = match files {
+            Some(files) => Outcome::Delivered(files),
+            None => Outcome::Failed,
+        };
+        self.outcome.send_replace(outcome);
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        self.in_flight.entries.lock().unwrap().remove(&self.key);
+    }
+}
+
+impl Follower {
+    /// Waits for the leader to finish.
+    pub(crate) async fn outcome(mut self) -> Outcome {
+        match self
+            .outcome
+            .wait_for(|outcome| *outcome != Outcome::Running)
+            .await
+        {
+            Ok(outcome) => outcome.clone(),
+            // The leader was dropped without finishing
+            Err(_) => Outcome::Failed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Claim, InFlight, Outcome};
+    use crate::send_audio::{SendMethod, SentFile};
+    use std::sync::Arc;
+
+    fn claim(in_flight: &Arc<InFlight>, chat_id: i64) -> Claim {
+        in_flight.claim("Youtube/abc/mp3-standard", chat_id, false)
+    }
+
+    #[tokio::test]
+    async fn followers_receive_the_leaders_files() {
+        let in_flight = Arc::new(InFlight::default());
+        let Claim::Leader(leader) = claim(&in_flight, 1) else {
+            panic!("first claim must lead");
+        };
+        let Claim::Follower(other_chat) = claim(&in_flight, 2) else {
+            panic!("second claim must follow");
+        };
+        let Claim::Follower(same_chat) = claim(&in_flight, 1) else {
+            panic!("third claim must follow");
+        };
+        assert!(!other_chat.same_chat);
+        assert!(same_chat.same_chat);
+
+        let files = vec![SentFile {
+            method: SendMethod::Audio,
+            file_id: "id".to_string(),
+            caption: "mp3".to_string(),
+        }];
+        leader.finish(Some(files.clone()));
+
+        assert_eq!(other_chat.outcome().await, Outcome::Delivered(files));
+        assert!(matches!(claim(&in_flight, 2), Claim::Leader(_)));
+    }
+
+    #[tokio::test]
+    async fn dropped_leader_counts_as_failure() {
+        let in_flight = Arc::new(InFlight::default());
+        let Claim::Leader(leader) = claim(&in_flight, 1) else {
+            panic!("first claim must lead");
+        };
+        let Claim::Follower(follower) = claim(&in_flight, 2) else {
+            panic!("second claim must follow");
+        };
+
+        drop(leader);
+
+        assert_eq!(follower.outcome().await, Outcome::Failed);
+    }
+
+    #[test]
+    fn playlist_items_do_not_follow_each_other() {
+        let in_flight = Arc::new(InFlight::default());
+        let key = "Youtube/abc/mp3-standard";
+        // A later entry of a playlist claimed the video first
+        let Claim::Leader(_later_item) = in_flight.claim(key, 1, true) else {
+            panic!("first claim must lead");
+        };
+
+        assert!(matches!(in_flight.claim(key, 1, true), Claim::Independent));
+        assert!(matches!(in_flight.claim(key, 2, false), Claim::Follower(_)));
+    }
+
+    #[test]
+    fn playlist_items_follow_single_downloads() {
+        let in_flight = Arc::new(InFlight::default());
+        let key = "Youtube/abc/mp3-standard";
+        let Claim::Leader(_single) = in_flight.claim(key, 1, false) else {
+            panic!("first claim must lead");
+        };
+
+        assert!(matches!(in_flight.claim(key, 2, true), Claim::Follower(_)));
+    }
+}
@@ -0,0 +1,428 @@
+use crate::download_options::DownloadOptions;
+use crate::storage;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// How many finished jobs are kept when the log is compacted on startup.
+/// Keeping a tail of history means job IDs keep increasing across restarts.
+const RETAINED_FINISHED_JOBS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum JobState {
+    /// Waiting for the user to pick a format from the inline keyboard.
+    AwaitingChoice,
+    Queued,
+    FetchingMetadata,
+    Downloading,
+    Uploading,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub(crate) fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            JobState::Done | JobState::Failed | JobState::Cancelled
+        )
+    }
+
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            JobState::AwaitingChoice => "waiting for format choice",
+            JobState::Queued => "queued",
+            JobState::FetchingMetadata => "fetching metadata",
+            JobState::Downloading => "downloading",
+            JobState::Uploading => "uploading",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Job {
+    pub(crate) id: u64,
+    pub(crate) chat_id: i64,
+    pub(crate) user_id: i64,
+    pub(crate) url: String,
+    #[serde(default)]
+    pub(crate) options: DownloadOptions,
+    pub(crate) state: JobState,
+    /// Number of times a worker has started processing this job.
+    pub(crate) attempts: u32,
+    pub(crate) status_message_id: Option<i64>,
+    /// Set for jobs downloading one entry of a playlist.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) playlist: Option<PlaylistItem>,
+}
+
+/// Position of a job within a playlist; items are delivered in `index` order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PlaylistItem {
+    pub(crate) playlist_id: u64,
+    pub(crate) index: usize,
+}
+
+/// Durable job queue backed by an append-only JSON lines file.
+///
+/// Every change appends a full snapshot of the job; on startup the log is
+/// replayed so the last snapshot of each job wins, then compacted.
+pub(crate) struct JobStore {
+    inner: Mutex<JobStoreInner>,
+}
+
+struct JobStoreInner {
+    file: File,
+    jobs: BTreeMap<u64, Job>,
+    next_id: u64,
+}
+
+impl JobStore {
+    pub(crate) fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut jobs = match File::open(path) {
+            Ok(file) => replay(file)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        let next_id = jobs.keys().next_back().map_or(1, |id| id + 1);
+        retain_recent_finished(&mut jobs);
+        compact(path, &jobs)?;
+
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(Self {
+            inner: Mutex::new(JobStoreInner {
+                file,
+                jobs,
+                next_id,
+            }),
+        })
+    }
+
+    /// Records a new job in `state` and returns it with its assigned ID.
+    pub(crate) fn create(
+        &self,
+        chat_id: i64,
+        user_id: i64,
+        url: &str,
+        options: DownloadOptions,
+        state: JobState,
+    ) -> io::Result<Job> {
+        let mut inner = self.inner.lock().unwrap();
+        let job = Job {
+            id: inner.next_id,
+            chat_id,
+            user_id,
+            url: url.to_string(),
+            options,
+            state,
+            attempts: 0,
+            status_message_id: None,
+            playlist: None,
+        };
+        inner.insert(job)
+    }
+
+    /// Records one queued job per URL of a playlist, in playlist order.
+    pub(crate) fn create_playlist_items(
+        &self,
+        chat_id: i64,
+        user_id: i64,
+        playlist_id: u64,
+        urls: &[String],
+        options: DownloadOptions,
+    ) -> io::Result<Vec<Job>> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut jobs = Vec::with_capacity(urls.len());
+        for (index, url) in urls.iter().enumerate() {
+            let job = Job {
+                id: inner.next_id,
+                chat_id,
+                user_id,
+                url: url.clone(),
+                options,
+                state: JobState::Queued,
+                attempts: 0,
+                status_message_id: None,
+                playlist: Some(PlaylistItem { playlist_id, index }),
+            };
+            jobs.push(inner.insert(job)?);
+        }
+        Ok(jobs)
+    }
+
+    /// Applies `change` to the job and persists the result.
+    ///
+    /// Persistence failures are logged rather than returned so that a full
+    /// disk does not abort a download that is already in progress.
+    pub(crate) fn update(&self, id: u64, change: impl FnOnce(&mut Job)) -> Option<Job> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut job = inner.jobs.get(&id)?.clone();
+        change(&mut job);
+        if let Err(e) = inner.append(&job) {
+            warn!("Failed to persist job {}: {}", id, e);
+        }
+        inner.jobs.insert(id, job.clone());
+        Some(job)
+    }
+
+    pub(crate) fn set_state(&self, id: u64, state: JobState) -> Option<Job> {
+        self.update(id, |job| job.state = state)
+    }
+
+    pub(crate) fn get(&self, id: u64) -> Option<Job> {
+        self.inner.lock().unwrap().jobs.get(&id).cloned()
+    }
+
+    /// Jobs that were not finished when the log was last written, oldest first.
+    pub(crate) fn unfinished(&self) -> Vec<Job> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .jobs
+            .values()
+            .filter(|job| !job.state.is_terminal())
+            .cloned()
+            .collect()
+    }
+}
+
+impl JobStoreInner {
+    fn insert(&mut self, job: Job) -> io::Result<Job> {
+        self.append(&job)?;
+        self.next_id += 1;
+        self.jobs.insert(job.id, job.clone());
+        Ok(job)
+    }
+
+    fn append(&mut self, job: &Job) -> io::Result<()> {
+        let mut line = serde_json::to_vec(job)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.flush()
+    }
+}
+
+fn replay(file: File) -> io::Result<BTreeMap<u64, Job>> {
+    let mut jobs = BTreeMap::new();
+    for (line_number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // A crash in the middle of a write can leave a truncated last line.
+        match serde_json::from_str::<Job>(&line) {
+            Ok(job) => {
+                jobs.insert(job.id, job);
+            }
+            Err(e) => warn!(
+                "Skipping malformed job record on line {}: {}",
+                line_number + 1,
+                e
+            ),
+        }
+    }
+    Ok(jobs)
+}
+
+fn retain_recent_finished(jobs: &mut BTreeMap<u64, Job>) {
+    let finished: Vec<u64> = jobs
+        .values()
+        .filter(|job| job.state.is_terminal())
+        .map(|job| job.id)
+        .collect();
+    let excess = finished.len().saturating_sub(RETAINED_FINISHED_JOBS);
+    for id in &finished[..excess] {
+        jobs.remove(id);
+    }
+}
+
+/// Rewrites the log with one record per job, replacing the old file atomically.
+fn compact(path: &Path, jobs: &BTreeMap<u64, Job>) -> io::Result<()> {
+    let mut contents = Vec::new();
+    for job in jobs.values() {
+        serde_json::to_writer(&mut contents, job)?;
+        contents.push(b'\n');
+    }
+    storage::write_atomically(path, &contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JobState, JobStore, PlaylistItem};
+    use crate::download_options::DownloadOptions;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    #[test]
+    fn jobs_are_replayed_after_reopening() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("jobs.jsonl");
+
+        {
+            let store = JobStore::open(&path).unwrap();
+            let first = store
+                .create(
+                    1,
+                    10,
+                    "https://example.com/a",
+                    DownloadOptions::default(),
+                    JobState::Queued,
+                )
+                .unwrap();
+            let second = store
+                .create(
+                    1,
+                    10,
+                    "https://example.com/b",
+                    DownloadOptions::default(),
+                    JobState::Queued,
+                )
+                .unwrap();
+            store.set_state(first.id, JobState::Done);
+            store.update(second.id, |job| {
+                job.state = JobState::Downloading;
+                job.attempts = 1;
+                job.status_message_id = Some(42);
+            });
+        }
+
+        let store = JobStore::open(&path).unwrap();
+        let unfinished = store.unfinished();
+
+        assert_eq!(unfinished.len(), 1);
+        assert_eq!(unfinished[0].url, "https://example.com/b");
+        assert_eq!(unfinished[0].state, JobState::Downloading);
+        assert_eq!(unfinished[0].attempts, 1);
+        assert_eq!(unfinished[0].status_message_id, Some(42));
+    }
+
+    #[test]
+    fn job_ids_keep_increasing_across_restarts() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("jobs.jsonl");
+
+        let first_id = {
+            let store = JobStore::open(&path).unwrap();
+            let job = store
+                .create(
+                    1,
+                    10,
+                    "https://example.com/a",
+                    DownloadOptions::default(),
+                    JobState::Queued,
+                )
+                .unwrap();
+            store.set_state(job.id, JobState::Failed);
+            job.id
+        };
+
+        let store = JobStore::open(&path).unwrap();
+        let job = store
+            .create(
+                1,
+                10,
+                "https://example.com/b",
+                DownloadOptions::default(),
+                JobState::Queued,
+            )
+            .unwrap();
+
+        assert!(job.id > first_id);
+    }
+
+    #[test]
+    fn truncated_last_record_is_skipped() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("jobs.jsonl");
+
+        {
+            let store = JobStore::open(&path).unwrap();
+            store
+                .create(
+                    1,
+                    10,
+                    "https://example.com/a",
+                    DownloadOptions::default(),
+                    JobState::Queued,
+                )
+                .unwrap();
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"{\"id\":2,\"chat_id\":1,\"user").unwrap();
+        drop(file);
+
+        let store = JobStore::open(&path).unwrap();
+
+        assert_eq!(store.unfinished().len(), 1);
+    }
+
+    #[test]
+    fn compaction_leaves_one_record_per_job() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("jobs.jsonl");
+
+        {
+            let store = JobStore::open(&path).unwrap();
+            let job = store
+                .create(
+                    1,
+                    10,
+                    "https://example.com/a",
+                    DownloadOptions::default(),
+                    JobState::Queued,
+                )
+                .unwrap();
+            store.set_state(job.id, JobState::FetchingMetadata);
+            store.set_state(job.id, JobState::Downloading);
+        }
+        JobStore::open(&path).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+    }
+
+    #[test]
+    fn playlist_items_are_created_in_order() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("jobs.jsonl");
+        let urls = vec![
+            "https://example.com/a".to_string(),
+            "https://example.com/b".to_string(),
+        ];
+
+        let jobs = JobStore::open(&path)
+            .unwrap()
+            .create_playlist_items(1, 10, 7, &urls, DownloadOptions::default())
+            .unwrap();
+        assert_eq!(jobs[0].id + 1, jobs[1].id);
+
+        let unfinished = JobStore::open(&path).unwrap().unfinished();
+        let items: Vec<_> = unfinished.iter().map(|job| job.playlist.unwrap()).collect();
+        assert_eq!(
+            items,
+            vec![
+                PlaylistItem {
+                    playlist_id: 7,
+                    index: 0
+                },
+                PlaylistItem {
+                    playlist_id: 7,
+                    index: 1
+                },
+            ]
+        );
+    }
+}
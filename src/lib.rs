@@ -1,4 +1,7 @@
 pub mod chunk_audio;
 
 // Re-export commonly used items
-pub use chunk_audio::{ChunkError, ChunkInfo, cleanup_chunks, needs_chunking, split_mp3};
+pub use chunk_audio::{
+    ChunkError, ChunkInfo, SplitConfig, cleanup_chunks, needs_chunking, split_by_duration,
+    split_mp3, split_mp3_with,
+};
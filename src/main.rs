@@ -1,146 +1,152 @@
 use crate::types::TelegramWebhook;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
 use axum::{Json, Router, routing::get, routing::post};
-use dotenv::dotenv;
-use serde_json::Value;
-use std::env;
-use tokio::process::Command;
+use std::sync::Arc;
 mod send_audio;
 use log::{error, info, warn};
-use send_audio::send_audio_to_telegram;
 
-mod chunk_audio;
+mod access_control;
+mod audio_format;
+mod chapters;
+mod commands;
+mod config;
+mod download;
+mod download_options;
+mod file_cache;
+mod format_choice;
+mod id3;
+mod in_flight;
+mod jobs;
+mod playlist;
+mod polling;
+mod preferences;
+mod progress;
+mod storage;
+mod telegram;
 mod telegram_status;
+mod thumbnail;
 mod types;
-use telegram_status::TelegramStatusMessage;
-
-fn env_bool_or_default(name: &str, default: bool) -> bool {
-    env::var(name)
-        .ok()
-        .and_then(|value| match value.trim().to_ascii_lowercase().as_str() {
-            "true" | "1" | "yes" | "y" | "on" => Some(true),
-            "false" | "0" | "no" | "n" | "off" => Some(false),
-            _ => None,
-        })
-        .unwrap_or(default)
+mod updates;
+mod video_format;
+mod webhook;
+mod work_dir;
+mod worker_pool;
+use access_control::AccessList;
+// Shared with the integration tests through the library target
+use config::{Config, UpdateMode};
+use file_cache::FileCache;
+use in_flight::InFlight;
+use jobs::JobStore;
+use playlist::PlaylistStore;
+use preferences::PreferenceStore;
+use telegram::TelegramClient;
+use worker_pool::WorkerPool;
+use yt_dl_service::chunk_audio;
+
+/// Shared state handed to every request handler and background job.
+#[derive(Clone)]
+pub(crate) struct AppState {
+    pub(crate) config: Arc<Config>,
+    /// Shares one connection pool across every Bot API call.
+    pub(crate) telegram: TelegramClient,
+    pub(crate) access: Arc<AccessList>,
+    pub(crate) jobs: Arc<JobStore>,
+    pub(crate) preferences: Arc<PreferenceStore>,
+    pub(crate) playlists: Arc<PlaylistStore>,
+    pub(crate) file_cache: Arc<FileCache>,
+    pub(crate) in_flight: Arc<InFlight>,
+    pub(crate) pool: Arc<WorkerPool>,
 }
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
-    let app = Router::new()
-        .route("/", get(|| async { "OK" }))
-        .route("/webhook", post(download_handler));
-
-    info!("YT DL Service starting...");
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
-}
-
-async fn download_handler(Json(payload): Json<TelegramWebhook>) {
-    dotenv().ok();
-    // Check if the message is from the allowed user
-    let allowed_user_id: i64 = env::var("ALLOWED_USER_ID")
-        .expect("ALLOWED_USER_ID must be set")
-        .parse()
-        .expect("ALLOWED_USER_ID must be a valid integer");
-
-    if payload.message.from.id != allowed_user_id {
-        warn!("Unauthorized user: {}", payload.message.from.id);
-        return;
+    let config = Config::from_env();
+    std::fs::create_dir_all(&config.data_dir).expect("DATA_DIR must be writable");
+    std::fs::create_dir_all(&config.downloads_dir).expect("DOWNLOADS_DIR must be writable");
+    // Unfinished jobs start over in fresh directories
+    work_dir::remove_stale(&config.downloads_dir);
+    let jobs = JobStore::open(config.data_dir.join("jobs.jsonl")).expect("Failed to open job log");
+    let access = AccessList::open(config.data_dir.join("access.json"), config.access.clone())
+        .expect("Failed to load access list");
+    if !access.has_admins() {
+        warn!("No admin configured; set ADMIN_USER_IDS to manage access with /allow and /deny");
     }
-
-    let Some(url) = payload.message.text else {
-        return;
+    let preferences = PreferenceStore::open(config.data_dir.join("preferences.json"))
+        .expect("Failed to load user preferences");
+    let playlists = PlaylistStore::open(config.data_dir.join("playlists.json"))
+        .expect("Failed to load playlists");
+    let file_cache = FileCache::open(config.data_dir.join("file_cache.json"))
+        .expect("Failed to load file cache");
+    let worker_count = config.max_concurrent_downloads;
+    let telegram = TelegramClient::new(&config.telegram_api_url, &config.bot_token);
+    let state = AppState {
+        config: Arc::new(config),
+        telegram,
+        access: Arc::new(access),
+        jobs: Arc::new(jobs),
+        preferences: Arc::new(preferences),
+        playlists: Arc::new(playlists),
+        file_cache: Arc::new(file_cache),
+        in_flight: Arc::new(InFlight::default()),
+        pool: Arc::new(WorkerPool::new()),
     };
 
-    info!("Received download request for URL: {}", url);
-
-    let bot_token = env::var("TELEGRAM_BOT_TOKEN").unwrap();
-    let force_ipv6 = env_bool_or_default("USE_IPV6", true);
-
-    tokio::spawn(async move {
-        let status =
-            TelegramStatusMessage::create(payload.message.chat.id, &bot_token, "Starting...").await;
+    playlist::reconcile(&state).await;
+    download::resume_unfinished_jobs(&state);
+    worker_pool::spawn_workers(&state, worker_count);
+
+    let mut app = Router::new().route("/", get(|| async { "OK" }));
+    match state.config.update_mode {
+        UpdateMode::Webhook => {
+            if let Some(webhook_url) = &state.config.webhook_url
+                && let Err(e) = webhook::register_webhook(
+                    &state.telegram,
+                    webhook_url,
+                    state.config.webhook_secret.as_deref(),
+                )
+                .await
+            {
+                error!("Failed to register webhook: {}", e);
+            }
 
-        // Step 1: get metadata
-        let mut metadata_command = Command::new("yt-dlp");
-        metadata_command.arg("-j");
-        if force_ipv6 {
-            metadata_command.arg("-6");
+            app = app.route(
+                "/webhook",
+                post(download_handler).route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    require_secret_token,
+                )),
+            );
         }
-        let output = metadata_command
-            .arg("--no-playlist")
-            .arg(&url)
-            .output()
-            .await;
-
-        let metadata: Option<Value> = output
-            .ok()
-            .and_then(|out| serde_json::from_slice(&out.stdout).ok());
-
-        let performer = metadata
-            .as_ref()
-            .and_then(|m| m.get("artist"))
-            .and_then(|a| a.as_str())
-            .unwrap_or("")
-            .to_string();
-
-        let title = metadata
-            .as_ref()
-            .and_then(|m| m.get("title"))
-            .and_then(|t| t.as_str())
-            .unwrap_or("Untitled")
-            .to_string();
-
-        // if artist is unknown, do not use it in the file name
-        let file_name = if performer.is_empty() {
-            format!("{}.mp3", title.replace(['/', '\\'], "_"))
-        } else {
-            format!("{} - {}.mp3", performer, title).replace(['/', '\\'], "_") // replace slashes and backslashes to avoid directory issues
-        };
-
-        let output_file = format!("./downloads/{}", file_name);
-        let mut download_command = Command::new("yt-dlp");
-        if force_ipv6 {
-            download_command.arg("-6");
+        UpdateMode::Polling => {
+            tokio::spawn(polling::run(state.clone()));
         }
-        status.update("Downloading and converting...").await;
-        let download_status = download_command
-            .arg("--no-playlist")
-            .arg("-v")
-            .arg("-x") // extract audio
-            .arg("--audio-format")
-            .arg("mp3") // convert to mp3
-            .arg("-o")
-            .arg(&output_file)
-            .arg(&url)
-            .status()
-            .await;
+    }
+    let app = app.with_state(state);
 
-        match download_status {
-            Ok(s) if s.success() => {
-                send_audio_to_telegram(
-                    payload.message.chat.id,
-                    &output_file,
-                    &performer,
-                    &title,
-                    &bot_token,
-                )
-                .await;
+    info!("YT DL Service starting...");
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
 
-                // This marks completion of the background workflow and upload
-                // attempts; send_audio_to_telegram does not confirm delivery.
-                status.delete().await;
-            }
-            Ok(s) => {
-                warn!("yt-dlp exited with status: {:?}", s);
-                status.update("Download failed").await;
-            }
-            Err(e) => {
-                error!("Failed to spawn yt-dlp for job {}: {}", file_name, e);
-                status.update("Download failed").await;
-            }
-        }
-    });
+/// Rejects webhook requests without the configured secret before the body is parsed.
+async fn require_secret_token(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !webhook::secret_matches(request.headers(), state.config.webhook_secret.as_deref()) {
+        warn!("Rejected webhook request with missing or invalid secret token");
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+async fn download_handler(State(state): State<AppState>, Json(payload): Json<TelegramWebhook>) {
+    updates::handle_update(&state, payload).await;
 }
@@ -0,0 +1,701 @@
+use crate::AppState;
+use crate::config::Config;
+use crate::download_options::DownloadOptions;
+use crate::format_choice;
+use crate::jobs::{Job, JobState};
+use crate::storage;
+use crate::telegram_status::TelegramStatusMessage;
+use crate::types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::process::Command;
+use tokio::sync::Notify;
+
+/// Prefix of the confirmation keyboard's callback data, e.g. `pl:3:start`.
+const CALLBACK_PREFIX: &str = "pl:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PlaylistState {
+    AwaitingConfirmation,
+    Running,
+    Finished,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ItemOutcome {
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct PlaylistEntry {
+    pub(crate) url: String,
+    pub(crate) title: Option<String>,
+    /// Seconds, when the site reports it without resolving the entry.
+    pub(crate) duration: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Playlist {
+    pub(crate) id: u64,
+    pub(crate) chat_id: i64,
+    pub(crate) user_id: i64,
+    pub(crate) title: String,
+    pub(crate) options: DownloadOptions,
+    pub(crate) state: PlaylistState,
+    pub(crate) entries: Vec<PlaylistEntry>,
+    /// Job of each entry, filled in when the download is confirmed.
+    #[serde(default)]
+    pub(crate) job_ids: Vec<u64>,
+    /// Result of each entry; `None` while it is queued or running.
+    #[serde(default)]
+    pub(crate) outcomes: Vec<Option<ItemOutcome>>,
+    pub(crate) status_message_id: Option<i64>,
+}
+
+impl Playlist {
+    /// Number of leading entries that are finished. The entry at this index
+    /// is the next one allowed to upload.
+    fn finished_prefix(&self) -> usize {
+        self.outcomes
+            .iter()
+            .take_while(|outcome| outcome.is_some())
+            .count()
+    }
+
+    fn count(&self, outcome: ItemOutcome) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|o| **o == Some(outcome))
+            .count()
+    }
+
+    /// Status line such as `12/40 done, 1 failed`.
+    pub(crate) fn progress_text(&self) -> String {
+        let mut text = format!(
+            "{}/{} done",
+            self.count(ItemOutcome::Done),
+            self.entries.len()
+        );
+        let failed = self.count(ItemOutcome::Failed);
+        if failed > 0 {
+            text.push_str(&format!(", {} failed", failed));
+        }
+        let cancelled = self.count(ItemOutcome::Cancelled);
+        if cancelled > 0 {
+            text.push_str(&format!(", {} cancelled", cancelled));
+        }
+        text
+    }
+
+    fn status_text(&self) -> String {
+        let prefix = match self.state {
+            PlaylistState::Finished => "Finished: ",
+            _ => "",
+        };
+        format!("{}\n{}{}", self.title, prefix, self.progress_text())
+    }
+
+    fn confirmation_text(&self) -> String {
+        let known: Vec<f64> = self.entries.iter().filter_map(|e| e.duration).collect();
+        let total = format_duration(known.iter().sum());
+        let duration = if known.is_empty() {
+            "unknown length".to_string()
+        } else if known.len() < self.entries.len() {
+            format!("at least {} total", total)
+        } else {
+            format!("{} total", total)
+        };
+        format!(
+            "Playlist: {}\n{} items, {}\nDownload as {}?",
+            self.title,
+            self.entries.len(),
+            duration,
+            self.options.describe()
+        )
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct PlaylistFile {
+    next_id: u64,
+    playlists: BTreeMap<u64, Playlist>,
+}
+
+/// Playlists awaiting confirmation or still downloading, persisted as JSON.
+/// Finished playlists are dropped when the store is opened.
+pub(crate) struct PlaylistStore {
+    path: PathBuf,
+    file: Mutex<PlaylistFile>,
+    /// Signalled whenever an item outcome is recorded.
+    changed: Notify,
+}
+
+impl PlaylistStore {
+    pub(crate) fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file: PlaylistFile = storage::load_json(&path)?;
+        file.playlists.retain(|_, playlist| {
+            matches!(
+                playlist.state,
+                PlaylistState::AwaitingConfirmation | PlaylistState::Running
+            )
+        });
+        // IDs are never reused, so a stale keyboard cannot start a newer playlist.
+        file.next_id = file.next_id.max(1);
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+            changed: Notify::new(),
+        })
+    }
+
+    pub(crate) fn create(
+        &self,
+        chat_id: i64,
+        user_id: i64,
+        title: String,
+        entries: Vec<PlaylistEntry>,
+        options: DownloadOptions,
+        status_message_id: Option<i64>,
+    ) -> io::Result<Playlist> {
+        let mut file = self.file.lock().unwrap();
+        let playlist = Playlist {
+            id: file.next_id,
+            chat_id,
+            user_id,
+            title,
+            options,
+            state: PlaylistState::AwaitingConfirmation,
+            entries,
+            job_ids: Vec::new(),
+            outcomes: Vec::new(),
+            status_message_id,
+        };
+        file.next_id += 1;
+        file.playlists.insert(playlist.id, playlist.clone());
+        storage::save_json(&self.path, &*file)?;
+        Ok(playlist)
+    }
+
+    pub(crate) fn get(&self, id: u64) -> Option<Playlist> {
+        self.file.lock().unwrap().playlists.get(&id).cloned()
+    }
+
+    pub(crate) fn running(&self) -> Vec<Playlist> {
+        self.file
+            .lock()
+            .unwrap()
+            .playlists
+            .values()
+            .filter(|playlist| playlist.state == PlaylistState::Running)
+            .cloned()
+            .collect()
+    }
+
+    /// Applies `change` to the playlist and persists the result. Persistence
+    /// failures are logged, as for jobs.
+    pub(crate) fn update(&self, id: u64, change: impl FnOnce(&mut Playlist)) -> Option<Playlist> {
+        let mut file = self.file.lock().unwrap();
+        let playlist = file.playlists.get_mut(&id)?;
+        change(playlist);
+        let playlist = playlist.clone();
+        if let Err(e) = storage::save_json(&self.path, &*file) {
+            warn!("Failed to persist playlist {}: {}", id, e);
+        }
+        Some(playlist)
+    }
+
+    /// Records the result of one entry and finishes the playlist once every
+    /// entry has one. Returns the playlist if the outcome was new.
+    pub(crate) fn record_outcome(
+        &self,
+        id: u64,
+        index: usize,
+        outcome: ItemOutcome,
+    ) -> Option<Playlist> {
+        let mut recorded = false;
+        let playlist = self.update(id, |playlist| {
+            let Some(slot) = playlist.outcomes.get_mut(index) else {
+                return;
+            };
+            if slot.is_some() {
+                return;
+            }
+            *slot = Some(outcome);
+            recorded = true;
+            if playlist.outcomes.iter().all(Option::is_some) {
+                playlist.state = PlaylistState::Finished;
+            }
+        });
+        self.changed.notify_waiters();
+        playlist.filter(|_| recorded)
+    }
+
+    /// Waits until every entry before `index` is finished, so uploads arrive
+    /// in playlist order. Earlier entries were queued first, so they are
+    /// always running or finished by the time a later one waits here.
+    pub(crate) async fn wait_for_turn(&self, id: u64, index: usize) {
+        loop {
+            let changed = self.changed.notified();
+            match self.get(id) {
+                Some(playlist)
+                    if playlist.state == PlaylistState::Running
+                        && playlist.finished_prefix() < index => {}
+                _ => return,
+            }
+            changed.await;
+        }
+    }
+}
+
+/// Whether callback data belongs to a playlist confirmation keyboard.
+pub(crate) fn is_playlist_callback(data: &str) -> bool {
+    data.starts_with(CALLBACK_PREFIX)
+}
+
+/// Parses `pl:{id}:start` or `pl:{id}:cancel`; `true` means start.
+fn parse_callback_data(data: &str) -> Option<(u64, bool)> {
+    let (id, action) = data.strip_prefix(CALLBACK_PREFIX)?.split_once(':')?;
+    let start = match action {
+        "start" => true,
+        "cancel" => false,
+        _ => return None,
+    };
+    Some((id.parse().ok()?, start))
+}
+
+fn confirmation_keyboard(playlist: &Playlist) -> InlineKeyboardMarkup {
+    let button = |text: String, action: &str| InlineKeyboardButton {
+        text,
+        callback_data: format!("{}{}:{}", CALLBACK_PREFIX, playlist.id, action),
+    };
+    InlineKeyboardMarkup {
+        inline_keyboard: vec![vec![
+            button(
+                format!("Download {} items", playlist.entries.len()),
+                "start",
+            ),
+            button("Cancel".to_string(), "cancel"),
+        ]],
+    }
+}
+
+/// Reads the title and entries from `yt-dlp --flat-playlist -J` output.
+/// Entries without a URL, such as private videos, are skipped.
+pub(crate) fn parse_flat_playlist(metadata: &Value) -> (String, Vec<PlaylistEntry>) {
+    let title = metadata
+        .get("title")
+        .and_then(Value::as_str)
+        .unwrap_or("Untitled playlist")
+        .to_string();
+    let entries = metadata
+        .get("entries")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let url = entry
+                .get("url")
+                .or_else(|| entry.get("webpage_url"))
+                .and_then(Value::as_str)?;
+            Some(PlaylistEntry {
+                url: url.to_string(),
+                title: entry.get("title").and_then(Value::as_str).map(String::from),
+                duration: entry.get("duration").and_then(Value::as_f64),
+            })
+        })
+        .collect();
+    (title, entries)
+}
+
+/// `h:mm:ss`, or `m:ss` below an hour.
+pub(crate) fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+async fn fetch_playlist(config: &Config, url: &str) -> Result<Value, String> {
+    let mut command = Command::new("yt-dlp");
+    command.kill_on_drop(true).arg("--flat-playlist").arg("-J");
+    if config.force_ipv6 {
+        command.arg("-6");
+    }
+    let output = command
+        .arg(url)
+        .output()
+        .await
+        .map_err(|e| format!("failed to run yt-dlp: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("yt-dlp exited with {}", output.status));
+    }
+    serde_json::from_slice(&output.stdout).map_err(|e| format!("invalid JSON: {}", e))
+}
+
+/// Enumerates a playlist and asks the user to confirm the download.
+pub(crate) async fn offer(
+    state: AppState,
+    chat_id: i64,
+    user_id: i64,
+    url: String,
+    options: DownloadOptions,
+) {
+    let telegram = &state.telegram;
+    let status = TelegramStatusMessage::create(telegram, chat_id, "Reading playlist...").await;
+
+    let (title, entries) = match fetch_playlist(&state.config, &url).await {
+        Ok(metadata) => parse_flat_playlist(&metadata),
+        Err(e) => {
+            warn!("Failed to read playlist {}: {}", url, e);
+            status.update("Could not read this playlist.").await;
+            return;
+        }
+    };
+    if entries.is_empty() {
+        status
+            .update("This playlist has no downloadable entries.")
+            .await;
+        return;
+    }
+
+    let playlist = match state.playlists.create(
+        chat_id,
+        user_id,
+        title,
+        entries,
+        options,
+        status.message_id(),
+    ) {
+        Ok(playlist) => playlist,
+        Err(e) => {
+            error!("Failed to record playlist {}: {}", url, e);
+            status.update("Failed to save the playlist.").await;
+            return;
+        }
+    };
+    info!(
+        "Playlist {} has {} entries: {}",
+        playlist.id,
+        playlist.entries.len(),
+        url
+    );
+    status
+        .update_with_keyboard(
+            &playlist.confirmation_text(),
+            &confirmation_keyboard(&playlist),
+        )
+        .await;
+}
+
+/// Handles a tap on the confirmation keyboard. Access is checked by the caller.
+pub(crate) async fn handle_callback(state: &AppState, query: CallbackQuery) {
+    let user_id = query.from.id;
+    let telegram = &state.telegram;
+    let Some((id, start)) = query.data.as_deref().and_then(parse_callback_data) else {
+        format_choice::answer(telegram, &query.id, Some("Unknown button.")).await;
+        return;
+    };
+
+    let mut accepted = false;
+    let playlist = match state.playlists.get(id) {
+        Some(playlist) if playlist.user_id == user_id || state.access.is_admin(user_id) => {
+            state.playlists.update(id, |playlist| {
+                if playlist.state != PlaylistState::AwaitingConfirmation {
+                    return;
+                }
+                accepted = true;
+                if start {
+                    playlist.state = PlaylistState::Running;
+                    playlist.outcomes = vec![None; playlist.entries.len()];
+                } else {
+                    playlist.state = PlaylistState::Cancelled;
+                }
+            })
+        }
+        Some(_) => {
+            format_choice::answer(
+                telegram,
+                &query.id,
+                Some("This playlist belongs to someone else."),
+            )
+            .await;
+            return;
+        }
+        None => None,
+    };
+
+    let Some(playlist) = playlist.filter(|_| accepted) else {
+        format_choice::answer(
+            telegram,
+            &query.id,
+            Some("This playlist was already started or cancelled."),
+        )
+        .await;
+        return;
+    };
+    format_choice::answer(telegram, &query.id, None).await;
+
+    if !start {
+        info!("User {} cancelled playlist {}", user_id, id);
+        TelegramStatusMessage::resume(
+            telegram,
+            playlist.chat_id,
+            playlist.status_message_id,
+            "Cancelled",
+        )
+        .await;
+        return;
+    }
+    // Editing without a keyboard removes the buttons.
+    TelegramStatusMessage::resume(
+        telegram,
+        playlist.chat_id,
+        playlist.status_message_id,
+        &playlist.status_text(),
+    )
+    .await;
+
+    let urls: Vec<String> = playlist.entries.iter().map(|e| e.url.clone()).collect();
+    let jobs = match state.jobs.create_playlist_items(
+        playlist.chat_id,
+        playlist.user_id,
+        id,
+        &urls,
+        playlist.options,
+    ) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            error!("Failed to record jobs for playlist {}: {}", id, e);
+            state
+                .playlists
+                .update(id, |playlist| playlist.state = PlaylistState::Cancelled);
+            TelegramStatusMessage::resume(
+                telegram,
+                playlist.chat_id,
+                playlist.status_message_id,
+                "Failed to queue the playlist.",
+            )
+            .await;
+            return;
+        }
+    };
+
+    let job_ids = jobs.iter().map(|job| job.id).collect();
+    state
+        .playlists
+        .update(id, |playlist| playlist.job_ids = job_ids);
+    info!("Playlist {} queued as {} jobs", id, jobs.len());
+    for job in jobs {
+        state.pool.enqueue(job);
+    }
+}
+
+/// Records that a playlist job reached `final_state` and refreshes the
+/// playlist's status message. Does nothing for other jobs.
+pub(crate) async fn item_finished(state: &AppState, job: &Job, final_state: JobState) {
+    let Some(item) = job.playlist else {
+        return;
+    };
+    let outcome = match final_state {
+        JobState::Done => ItemOutcome::Done,
+        JobState::Failed => ItemOutcome::Failed,
+        JobState::Cancelled => ItemOutcome::Cancelled,
+        _ => return,
+    };
+    let Some(playlist) = state
+        .playlists
+        .record_outcome(item.playlist_id, item.index, outcome)
+    else {
+        return;
+    };
+
+    if playlist.state == PlaylistState::Finished {
+        info!(
+            "Playlist {} finished: {}",
+            playlist.id,
+            playlist.progress_text()
+        );
+    }
+    TelegramStatusMessage::resume(
+        &state.telegram,
+        playlist.chat_id,
+        playlist.status_message_id,
+        &playlist.status_text(),
+    )
+    .await;
+}
+
+/// Fills in outcomes lost to a restart: an item whose job already finished,
+/// or was dropped from the job log, would otherwise block later uploads.
+pub(crate) async fn reconcile(state: &AppState) {
+    for playlist in state.playlists.running() {
+        for (index, job_id) in playlist.job_ids.iter().enumerate() {
+            if playlist.outcomes.get(index).is_some_and(Option::is_some) {
+                continue;
+            }
+            match state.jobs.get(*job_id) {
+                Some(job) if job.state.is_terminal() => {
+                    item_finished(state, &job, job.state).await;
+                }
+                Some(_) => {}
+                None => {
+                    state
+                        .playlists
+                        .record_outcome(playlist.id, index, ItemOutcome::Failed);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ItemOutcome, PlaylistEntry, PlaylistState, PlaylistStore, format_duration,
+        parse_callback_data, parse_flat_playlist,
+    };
+    use crate::download_options::DownloadOptions;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn entries(count: usize) -> Vec<PlaylistEntry> {
+        (0..count)
+            .map(|i| PlaylistEntry {
+                url: format!("https://example.com/{}", i),
+                title: None,
+                duration: Some(60.0),
+            })
+            .collect()
+    }
+
+    fn running_store(dir: &TempDir, count: usize) -> PlaylistStore {
+        let store = PlaylistStore::open(dir.path().join("playlists.json")).unwrap();
+        let playlist = store
+            .create(
+                1,
+                1,
+                "Mix".to_string(),
+                entries(count),
+                DownloadOptions::default(),
+                Some(5),
+            )
+            .unwrap();
+        store.update(playlist.id, |playlist| {
+            playlist.state = PlaylistState::Running;
+            playlist.outcomes = vec![None; count];
+        });
+        store
+    }
+
+    #[test]
+    fn flat_playlist_entries_are_read_in_order() {
+        let metadata = json!({
+            "title": "Album",
+            "entries": [
+                { "url": "https://youtu.be/a", "title": "One", "duration": 61.0 },
+                { "title": "[Private video]" },
+                { "webpage_url": "https://youtu.be/b" }
+            ]
+        });
+
+        let (title, entries) = parse_flat_playlist(&metadata);
+
+        assert_eq!(title, "Album");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].title.as_deref(), Some("One"));
+        assert_eq!(entries[0].duration, Some(61.0));
+        assert_eq!(entries[1].url, "https://youtu.be/b");
+    }
+
+    #[test]
+    fn durations_are_formatted() {
+        assert_eq!(format_duration(59.6), "1:00");
+        assert_eq!(format_duration(3723.0), "1:02:03");
+    }
+
+    #[test]
+    fn callback_data_is_parsed() {
+        assert_eq!(parse_callback_data("pl:3:start"), Some((3, true)));
+        assert_eq!(parse_callback_data("pl:3:cancel"), Some((3, false)));
+        assert_eq!(parse_callback_data("pl:3:maybe"), None);
+        assert_eq!(parse_callback_data("3:mp3"), None);
+    }
+
+    #[test]
+    fn progress_counts_outcomes() {
+        let dir = TempDir::new().unwrap();
+        let store = running_store(&dir, 3);
+
+        store.record_outcome(1, 0, ItemOutcome::Done);
+        let playlist = store.record_outcome(1, 2, ItemOutcome::Failed).unwrap();
+        assert_eq!(playlist.progress_text(), "1/3 done, 1 failed");
+        assert_eq!(playlist.state, PlaylistState::Running);
+
+        let playlist = store.record_outcome(1, 1, ItemOutcome::Done).unwrap();
+        assert_eq!(playlist.state, PlaylistState::Finished);
+        assert!(store.record_outcome(1, 1, ItemOutcome::Failed).is_none());
+    }
+
+    #[test]
+    fn finished_playlists_are_dropped_but_ids_are_not_reused() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("playlists.json");
+        {
+            let store = running_store(&dir, 1);
+            store.record_outcome(1, 0, ItemOutcome::Done);
+        }
+
+        let store = PlaylistStore::open(&path).unwrap();
+        assert!(store.get(1).is_none());
+        let playlist = store
+            .create(
+                1,
+                1,
+                "Next".to_string(),
+                entries(1),
+                DownloadOptions::default(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(playlist.id, 2);
+    }
+
+    #[tokio::test]
+    async fn later_items_wait_for_earlier_ones() {
+        let dir = TempDir::new().unwrap();
+        let store = Arc::new(running_store(&dir, 3));
+
+        // The first item never waits
+        store.wait_for_turn(1, 0).await;
+
+        let waiting = tokio::spawn({
+            let store = store.clone();
+            async move { store.wait_for_turn(1, 2).await }
+        });
+        store.record_outcome(1, 1, ItemOutcome::Done);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        store.record_outcome(1, 0, ItemOutcome::Cancelled);
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+use crate::AppState;
+use crate::storage;
+use crate::types::TelegramWebhook;
+use crate::updates::handle_update;
+use log::{error, info, warn};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long Telegram holds a `getUpdates` request open when there is nothing to deliver.
+const LONG_POLL_TIMEOUT_SECS: u64 = 50;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Persists the `getUpdates` offset so updates are not processed twice after a restart.
+struct OffsetStore {
+    path: PathBuf,
+}
+
+impl OffsetStore {
+    fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    fn load(&self) -> Option<i64> {
+        let contents = std::fs::read_to_string(&self.path).ok()?;
+        match contents.trim().parse() {
+            Ok(offset) => Some(offset),
+            Err(e) => {
+                warn!(
+                    "Ignoring invalid update offset in {}: {}",
+                    self.path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    fn save(&self, offset: i64) {
+        if let Err(e) = storage::write_atomically(&self.path, offset.to_string().as_bytes()) {
+            error!("Failed to persist update offset {}: {}", offset, e);
+        }
+    }
+}
+
+/// Receives updates with `getUpdates` long polling instead of the webhook.
+/// Runs until the process exits.
+pub(crate) async fn run(state: AppState) {
+    let offsets = OffsetStore::new(state.config.data_dir.join("update_offset"));
+    let mut offset = offsets.load();
+
+    // Telegram refuses getUpdates while a webhook is registered.
+    if let Err(e) = state.telegram.delete_webhook().await {
+        warn!("Failed to remove webhook before polling: {}", e);
+    }
+
+    info!("Polling for updates (offset {:?})", offset);
+    loop {
+        let updates = match state
+            .telegram
+            .get_updates(offset, LONG_POLL_TIMEOUT_SECS)
+            .await
+        {
+            Ok(updates) => updates,
+            Err(e) => {
+                error!("getUpdates failed: {}", e);
+                tokio::time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+        };
+
+        for update in updates {
+            let Some(update_id) = update.get("update_id").and_then(Value::as_i64) else {
+                warn!("Skipping update without update_id");
+                continue;
+            };
+
+            match serde_json::from_value::<TelegramWebhook>(update) {
+                Ok(update) => handle_update(&state, update).await,
+                Err(e) => warn!(
+                    "Skipping update {} that could not be parsed: {}",
+                    update_id, e
+                ),
+            }
+
+            // Acknowledge only after handling, so a crash replays the update
+            // instead of losing it.
+            offset = Some(update_id + 1);
+            offsets.save(update_id + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OffsetStore;
+    use tempfile::TempDir;
+
+    #[test]
+    fn offset_survives_reopening() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("update_offset");
+
+        assert_eq!(OffsetStore::new(&path).load(), None);
+        OffsetStore::new(&path).save(4242);
+
+        assert_eq!(OffsetStore::new(&path).load(), Some(4242));
+    }
+}
@@ -0,0 +1,94 @@
+use crate::download_options::DownloadOptions;
+use crate::storage;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Per-user download defaults, persisted as JSON keyed by user ID.
+pub(crate) struct PreferenceStore {
+    path: PathBuf,
+    users: Mutex<BTreeMap<i64, DownloadOptions>>,
+}
+
+impl PreferenceStore {
+    pub(crate) fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let users = storage::load_json(&path)?;
+        Ok(Self {
+            path,
+            users: Mutex::new(users),
+        })
+    }
+
+    pub(crate) fn get(&self, user_id: i64) -> DownloadOptions {
+        self.users
+            .lock()
+            .unwrap()
+            .get(&user_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Applies `change` to the user's defaults and saves them.
+    pub(crate) fn update(
+        &self,
+        user_id: i64,
+        change: impl FnOnce(&mut DownloadOptions),
+    ) -> io::Result<DownloadOptions> {
+        let mut users = self.users.lock().unwrap();
+        let mut updated = users.clone();
+        let options = updated.entry(user_id).or_default();
+        change(options);
+        let options = *options;
+        storage::save_json(&self.path, &updated)?;
+        *users = updated;
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PreferenceStore;
+    use crate::audio_format::{AudioFormat, AudioQuality};
+    use tempfile::TempDir;
+
+    #[test]
+    fn unknown_user_gets_defaults() {
+        let dir = TempDir::new().unwrap();
+        let store = PreferenceStore::open(dir.path().join("preferences.json")).unwrap();
+
+        assert_eq!(store.get(1).format, AudioFormat::Mp3);
+    }
+
+    #[test]
+    fn quality_is_stored_alongside_format() {
+        let dir = TempDir::new().unwrap();
+        let store = PreferenceStore::open(dir.path().join("preferences.json")).unwrap();
+
+        store
+            .update(1, |options| options.format = AudioFormat::Opus)
+            .unwrap();
+        let options = store
+            .update(1, |options| options.quality = AudioQuality::Voice)
+            .unwrap();
+
+        assert_eq!(options.format, AudioFormat::Opus);
+        assert_eq!(options.quality, AudioQuality::Voice);
+    }
+
+    #[test]
+    fn preferences_survive_reopening() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("preferences.json");
+
+        PreferenceStore::open(&path)
+            .unwrap()
+            .update(1, |options| options.format = AudioFormat::Opus)
+            .unwrap();
+
+        let store = PreferenceStore::open(&path).unwrap();
+        assert_eq!(store.get(1).format, AudioFormat::Opus);
+        assert_eq!(store.get(2).format, AudioFormat::Mp3);
+    }
+}
@@ -0,0 +1,235 @@
+use std::time::Duration;
+
+/// Starts every line yt-dlp prints from our progress templates, so they can
+/// be told apart from its regular log.
+const MARKER: &str = "[yt-dl-service]";
+
+/// Arguments making yt-dlp print one parseable line per progress update.
+/// `--progress` keeps the lines coming although `--print` implies `--quiet`.
+pub(crate) fn yt_dlp_args() -> [String; 6] {
+    [
+        "--newline".to_string(),
+        "--progress".to_string(),
+        "--progress-template".to_string(),
+        format!(
+            "download:{} download %(progress.downloaded_bytes)s %(progress.total_bytes)s \
+             %(progress.total_bytes_estimate)s %(progress.speed)s %(progress.eta)s",
+            MARKER
+        ),
+        "--progress-template".to_string(),
+        format!(
+            "postprocess:{} postprocess %(progress.status)s %(progress.postprocessor)s",
+            MARKER
+        ),
+    ]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ProgressEvent {
+    Download(DownloadProgress),
+    /// A postprocessor such as `FFmpegExtractAudio` started.
+    Postprocess(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DownloadProgress {
+    pub(crate) downloaded_bytes: u64,
+    /// Exact size, or yt-dlp's estimate when the size is not known up front.
+    pub(crate) total_bytes: Option<u64>,
+    /// Bytes per second.
+    pub(crate) speed: Option<f64>,
+    pub(crate) eta_secs: Option<u64>,
+}
+
+impl DownloadProgress {
+    /// Status text such as `Downloading 45% of 12.3 MB, 1.2 MB/s, 0:42 left`.
+    pub(crate) fn describe(&self) -> String {
+        let mut text = match self.total_bytes.filter(|&total| total > 0) {
+            Some(total) => format!(
+                "Downloading {}% of {}",
+                (self.downloaded_bytes * 100 / total).min(100),
+                format_bytes(total)
+            ),
+            None => format!("Downloading {}", format_bytes(self.downloaded_bytes)),
+        };
+        if let Some(speed) = self.speed {
+            text.push_str(&format!(", {}/s", format_bytes(speed as u64)));
+        }
+        if let Some(eta) = self.eta_secs {
+            text.push_str(&format!(", {} left", format_duration(eta)));
+        }
+        text
+    }
+}
+
+/// Parses a line printed from `yt_dlp_args`' templates; `None` for any other line.
+pub(crate) fn parse_line(line: &str) -> Option<ProgressEvent> {
+    let mut fields = line.trim().strip_prefix(MARKER)?.split_whitespace();
+    match fields.next()? {
+        "download" => {
+            let downloaded_bytes = number(fields.next()?)? as u64;
+            let total_bytes = number(fields.next()?);
+            let estimate = number(fields.next()?);
+            Some(ProgressEvent::Download(DownloadProgress {
+                downloaded_bytes,
+                total_bytes: total_bytes.or(estimate).map(|total| total as u64),
+                speed: number(fields.next()?),
+                eta_secs: number(fields.next()?).map(|eta| eta as u64),
+            }))
+        }
+        "postprocess" => match fields.next()? {
+            "started" => Some(ProgressEvent::Postprocess(fields.next()?.to_string())),
+            // Other statuses mark it running or finished; the next stage
+            // reports itself
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Status text for a postprocessor, or `None` if it is too quick to show.
+pub(crate) fn describe_postprocessor(name: &str) -> Option<&'static str> {
+    name.starts_with("FFmpeg")
+        .then_some("Converting with ffmpeg...")
+}
+
+/// Status text such as `Uploading part 2/3: 45% of 48.0 MB, 1.2 MB/s` for
+/// an upload `label` that has sent `sent_bytes` in `elapsed`.
+pub(crate) fn describe_upload(
+    label: &str,
+    sent_bytes: u64,
+    total_bytes: u64,
+    elapsed: Duration,
+) -> String {
+    let percent = (sent_bytes * 100).checked_div(total_bytes).unwrap_or(100);
+    let mut text = format!(
+        "{}: {}% of {}",
+        label,
+        percent.min(100),
+        format_bytes(total_bytes)
+    );
+    if !elapsed.is_zero() {
+        let speed = sent_bytes as f64 / elapsed.as_secs_f64();
+        text.push_str(&format!(", {}/s", format_bytes(speed as u64)));
+    }
+    text
+}
+
+/// yt-dlp prints `NA` for fields it does not know.
+fn number(field: &str) -> Option<f64> {
+    field.parse().ok().filter(|value: &f64| value.is_finite())
+}
+
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        DownloadProgress, ProgressEvent, describe_postprocessor, describe_upload, parse_line,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn download_lines_are_parsed() {
+        let event = parse_line("[yt-dl-service] download 5767168 12897484 NA 1258291.2 6");
+
+        assert_eq!(
+            event,
+            Some(ProgressEvent::Download(DownloadProgress {
+                downloaded_bytes: 5_767_168,
+                total_bytes: Some(12_897_484),
+                speed: Some(1_258_291.2),
+                eta_secs: Some(6),
+            }))
+        );
+    }
+
+    #[test]
+    fn estimate_stands_in_for_unknown_size() {
+        let Some(ProgressEvent::Download(progress)) =
+            parse_line("[yt-dl-service] download 1024 NA 4096.0 NA NA")
+        else {
+            panic!("line must parse");
+        };
+
+        assert_eq!(progress.total_bytes, Some(4096));
+        assert_eq!(progress.speed, None);
+        assert_eq!(progress.describe(), "Downloading 25% of 4.0 KB");
+    }
+
+    #[test]
+    fn other_lines_are_ignored() {
+        assert_eq!(parse_line("[download] Destination: Song.webm"), None);
+        assert_eq!(parse_line("/downloads/job-1/Song.mp3"), None);
+        assert_eq!(
+            parse_line("[yt-dl-service] postprocess finished FFmpegExtractAudio"),
+            None
+        );
+        assert_eq!(
+            parse_line("[yt-dl-service] postprocess started FFmpegExtractAudio"),
+            Some(ProgressEvent::Postprocess("FFmpegExtractAudio".to_string()))
+        );
+    }
+
+    #[test]
+    fn progress_is_described_with_speed_and_eta() {
+        let progress = DownloadProgress {
+            downloaded_bytes: 5_767_168,
+            total_bytes: Some(12_897_484),
+            speed: Some(1_258_291.2),
+            eta_secs: Some(3725),
+        };
+
+        assert_eq!(
+            progress.describe(),
+            "Downloading 44% of 12.3 MB, 1.2 MB/s, 1:02:05 left"
+        );
+    }
+
+    #[test]
+    fn only_ffmpeg_postprocessors_are_shown() {
+        assert_eq!(
+            describe_postprocessor("FFmpegExtractAudio"),
+            Some("Converting with ffmpeg...")
+        );
+        assert_eq!(describe_postprocessor("MoveFiles"), None);
+    }
+
+    #[test]
+    fn upload_is_described_with_average_speed() {
+        assert_eq!(
+            describe_upload(
+                "Uploading part 2/3",
+                22_020_096,
+                50_331_648,
+                Duration::from_secs(14)
+            ),
+            "Uploading part 2/3: 43% of 48.0 MB, 1.5 MB/s"
+        );
+        assert_eq!(
+            describe_upload("Uploading", 0, 1024, Duration::ZERO),
+            "Uploading: 0% of 1.0 KB"
+        );
+    }
+}
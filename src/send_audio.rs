@@ -1,144 +1,930 @@
-use crate::chunk_audio::{cleanup_chunks, needs_chunking, split_mp3};
-use log::{error, info};
-use reqwest::{Client, multipart};
+use crate::chunk_audio::{
+    ChunkError, ChunkInfo, SplitConfig, cleanup_chunks, probe_duration, split_by_duration_with,
+    split_mp3_with,
+};
+use crate::config::Config;
+use crate::progress;
+use crate::telegram::{ChatAction, Message, SendChatAction, TelegramClient, TelegramError};
+use crate::telegram_status::TelegramStatusMessage;
+use crate::video_format::{VideoInfo, probe_video};
+use log::{error, info, warn};
+use reqwest::multipart;
+use serde::{Deserialize, Serialize};
+use std::io;
 use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use tokio::fs;
+use tokio::io::{AsyncRead, ReadBuf};
 use tokio_util::codec::{BytesCodec, FramedRead};
 
-async fn send_single_chunk(
-    chat_id: i64,
-    path: &str,
-    performer: &str,
-    title: &str,
-    bot_token: &str,
-    should_delete: bool,
-) {
-    let client = Client::new();
-    let url = format!("https://api.telegram.org/bot{}/sendAudio", bot_token);
+/// Uploads are given up after this many attempts.
+const MAX_SEND_ATTEMPTS: u32 = 4;
+/// Wait after the first network failure, doubled for each further one.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(2);
+/// How often a running upload is reported. Telegram shows a chat action for
+/// five seconds, so it is sent again before it runs out.
+const UPLOAD_REPORT_INTERVAL: Duration = Duration::from_secs(4);
 
-    let file = match tokio::fs::File::open(path).await {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("Failed to open file {}: {}", path, e);
-            return;
+/// Telegram method used to deliver a file, chosen from its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SendMethod {
+    /// `sendAudio` shows a music player, but only accepts MP3 and M4A.
+    Audio,
+    /// `sendVoice` plays OGG/Opus inline.
+    Voice,
+    /// `sendVideo` plays MP4 inline.
+    Video,
+    /// `sendDocument` accepts anything, e.g. FLAC or WebM audio.
+    Document,
+}
+
+impl SendMethod {
+    pub(crate) fn for_extension(extension: &str) -> Self {
+        match extension.to_ascii_lowercase().as_str() {
+            "mp3" | "m4a" => SendMethod::Audio,
+            "opus" | "ogg" => SendMethod::Voice,
+            "mp4" => SendMethod::Video,
+            _ => SendMethod::Document,
         }
-    };
+    }
 
-    let stream = FramedRead::new(file, BytesCodec::new());
-    let file_body = reqwest::Body::wrap_stream(stream);
+    fn api_method(self) -> &'static str {
+        match self {
+            SendMethod::Audio => "sendAudio",
+            SendMethod::Voice => "sendVoice",
+            SendMethod::Video => "sendVideo",
+            SendMethod::Document => "sendDocument",
+        }
+    }
 
-    let file_name = Path::new(path)
-        .file_name()
-        .map(|name| name.to_string_lossy().to_string())
-        .unwrap_or_else(|| "audio.mp3".to_string());
+    /// `sendVideo` gets its duration from `probe_video` with the dimensions.
+    fn has_duration_field(self) -> bool {
+        matches!(self, SendMethod::Audio | SendMethod::Voice)
+    }
+
+    fn has_thumbnail_field(self) -> bool {
+        !matches!(self, SendMethod::Voice)
+    }
+
+    fn chat_action(self) -> ChatAction {
+        match self {
+            SendMethod::Voice => ChatAction::Voice,
+            SendMethod::Video => ChatAction::Video,
+            SendMethod::Audio | SendMethod::Document => ChatAction::Document,
+        }
+    }
+
+    fn file_field(self) -> &'static str {
+        match self {
+            SendMethod::Audio => "audio",
+            SendMethod::Voice => "voice",
+            SendMethod::Video => "video",
+            SendMethod::Document => "document",
+        }
+    }
+}
+
+fn extension_of(path: &str) -> &str {
+    Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+}
+
+/// MP3 is split on frame boundaries; other containers are cut by time with ffmpeg.
+async fn split_file(path: &str, config: SplitConfig) -> Result<Vec<ChunkInfo>, ChunkError> {
+    if extension_of(path).eq_ignore_ascii_case("mp3") {
+        split_mp3_with(path, config).await
+    } else {
+        split_by_duration_with(path, config).await
+    }
+}
+
+/// Descriptive fields sent along with a file.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MediaInfo<'a> {
+    pub(crate) performer: &'a str,
+    pub(crate) title: &'a str,
+    pub(crate) caption: &'a str,
+    /// JPEG within Telegram's thumbnail limits (320x320, 200 kB).
+    pub(crate) thumbnail: Option<&'a [u8]>,
+    /// Playing time in seconds when already known; probed otherwise.
+    pub(crate) duration: Option<f64>,
+}
+
+/// A file Telegram stored, which can be sent again by its `file_id`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SentFile {
+    /// Matches the kind Telegram stored, which a resend must use.
+    pub(crate) method: SendMethod,
+    pub(crate) file_id: String,
+    pub(crate) caption: String,
+}
+
+/// Voice messages, videos and documents have no title fields, so the title
+/// goes into the caption as well.
+fn caption_for(method: SendMethod, media: &MediaInfo<'_>) -> String {
+    match method {
+        SendMethod::Audio => media.caption.to_string(),
+        SendMethod::Voice | SendMethod::Video | SendMethod::Document => {
+            format!("{}\n{}", media.title, media.caption)
+        }
+    }
+}
+
+/// The file Telegram stored for a message sent from `path`.
+fn sent_file(message: &Message, path: &str, media: &MediaInfo<'_>) -> Option<SentFile> {
+    let (method, stored) = [
+        (SendMethod::Audio, &message.audio),
+        (SendMethod::Voice, &message.voice),
+        (SendMethod::Video, &message.video),
+        (SendMethod::Document, &message.document),
+    ]
+    .into_iter()
+    .find_map(|(method, stored)| Some((method, stored.as_ref()?)))?;
+    Some(SentFile {
+        method,
+        file_id: stored.file_id.clone(),
+        caption: caption_for(SendMethod::for_extension(extension_of(path)), media),
+    })
+}
+
+/// Counts the bytes read from a file into an upload body.
+struct CountingReader<R> {
+    inner: R,
+    read_bytes: Arc<AtomicU64>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        self.read_bytes.fetch_add(read as u64, Ordering::Relaxed);
+        poll
+    }
+}
+
+/// A file upload in progress, shown on the status message while it runs.
+struct UploadProgress<'a> {
+    status: &'a TelegramStatusMessage,
+    label: &'a str,
+    total_bytes: u64,
+    /// Bytes handed to the HTTP client in the current attempt.
+    sent_bytes: Arc<AtomicU64>,
+}
+
+impl UploadProgress<'_> {
+    fn describe(&self, started: Instant) -> String {
+        progress::describe_upload(
+            self.label,
+            self.sent_bytes.load(Ordering::Relaxed),
+            self.total_bytes,
+            started.elapsed(),
+        )
+    }
+}
+
+/// Builds the request for one attempt. The file is opened anew each time,
+/// since a stream consumed by a failed upload cannot be replayed. Bytes read
+/// from it are added to `sent_bytes`.
+async fn build_form(
+    local_api: bool,
+    chat_id: i64,
+    path: &str,
+    method: SendMethod,
+    media: &MediaInfo<'_>,
+    video: Option<&VideoInfo>,
+    sent_bytes: &Arc<AtomicU64>,
+) -> Result<multipart::Form, String> {
+    let MediaInfo {
+        performer,
+        title,
+        thumbnail,
+        duration,
+        ..
+    } = *media;
 
     let form = multipart::Form::new()
         .text("chat_id", chat_id.to_string())
-        .text("performer", performer.to_string())
-        .text("title", title.to_string())
-        .part(
-            "audio",
+        .text("caption", caption_for(method, media));
+    let form = match method {
+        SendMethod::Audio => form
+            .text("performer", performer.to_string())
+            .text("title", title.to_string()),
+        SendMethod::Video => {
+            let mut form = form.text("supports_streaming", "true");
+            if let Some(info) = video {
+                for (field, value) in [
+                    ("width", info.width),
+                    ("height", info.height),
+                    ("duration", info.duration),
+                ] {
+                    if let Some(value) = value {
+                        form = form.text(field, value.to_string());
+                    }
+                }
+            }
+            form
+        }
+        SendMethod::Voice | SendMethod::Document => form,
+    };
+    let mut form = if local_api {
+        // A local Bot API server reads the file itself instead of receiving it
+        let absolute_path = fs::canonicalize(path)
+            .await
+            .map_err(|e| format!("Failed to resolve file {}: {}", path, e))?;
+        form.text(
+            method.file_field(),
+            format!("file://{}", absolute_path.display()),
+        )
+    } else {
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|e| format!("Failed to open file {}: {}", path, e))?;
+
+        let file = CountingReader {
+            inner: file,
+            read_bytes: Arc::clone(sent_bytes),
+        };
+        let stream = FramedRead::new(file, BytesCodec::new());
+        let file_body = reqwest::Body::wrap_stream(stream);
+
+        let file_name = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "audio.mp3".to_string());
+        form.part(
+            method.file_field(),
             multipart::Part::stream(file_body).file_name(file_name),
-        );
+        )
+    };
+    if let Some(duration) = duration {
+        form = form.text("duration", (duration.round() as u64).to_string());
+    }
+    if method.has_thumbnail_field()
+        && let Some(thumbnail) = thumbnail
+    {
+        let part = multipart::Part::bytes(thumbnail.to_vec())
+            .file_name("thumbnail.jpg")
+            .mime_str("image/jpeg")
+            .expect("static MIME type is valid");
+        form = form.part("thumbnail", part);
+    }
+    Ok(form)
+}
 
-    match client.post(&url).multipart(form).send().await {
-        Ok(res) if res.status().is_success() => {
-            info!("Audio sent successfully to Telegram.");
-            // Only delete if this is a non-chunked file
-            // Chunks are deleted by cleanup_chunks() function
-            if should_delete {
-                let _ = tokio::fs::remove_file(&path).await;
-                info!("Deleted file: {}", path);
-            }
+/// How long to wait before retrying after `attempt` failed, or `None` if
+/// sending again would fail the same way.
+fn retry_delay(error: &TelegramError, attempt: u32) -> Option<Duration> {
+    match error {
+        TelegramError::Api {
+            retry_after: Some(secs),
+            ..
+        } => Some(Duration::from_secs(*secs)),
+        // Errors on Telegram's side, such as 502 Bad Gateway, pass like
+        // network failures
+        TelegramError::Api {
+            error_code: Some(500..=599),
+            ..
         }
-        Ok(res) => {
-            let status = res.status();
-            let body = res.text().await.unwrap_or_else(|_| "Unknown error".into());
-            error!("Telegram API error {}: {}", status, body);
+        | TelegramError::Transport(_) => Some(INITIAL_RETRY_DELAY * 2u32.pow(attempt - 1)),
+        // Rejected requests such as "Bad Request: file is too big"
+        TelegramError::Api { .. } => None,
+    }
+}
+
+/// Shows the upload in the chat until the request completes: the chat
+/// action, and the share of the file sent so far if `progress` is given.
+async fn report_upload(
+    telegram: &TelegramClient,
+    chat_id: i64,
+    method: SendMethod,
+    progress: Option<&UploadProgress<'_>>,
+    started: Instant,
+) {
+    let action = SendChatAction {
+        chat_id,
+        action: method.chat_action(),
+    };
+    let mut ticks = tokio::time::interval(UPLOAD_REPORT_INTERVAL);
+    loop {
+        ticks.tick().await;
+        if let Err(e) = telegram.send_chat_action(&action).await {
+            warn!("Failed to show upload status: {}", e);
         }
-        Err(e) => {
-            error!("Failed to send audio: {}", e);
+        if let Some(progress) = progress {
+            progress.status.progress(&progress.describe(started)).await;
         }
     }
 }
 
-pub async fn send_audio_to_telegram(
+/// Sends the form from `build_form` until Telegram accepts it, retrying
+/// rate limits and network failures. `label` names the file in the log.
+async fn send_with_retry<F>(
+    telegram: &TelegramClient,
     chat_id: i64,
-    path: &str,
-    performer: &str,
-    title: &str,
-    bot_token: &str,
-) {
-    // Check file size and handle chunking transparently
-    match fs::metadata(path).await {
-        Ok(metadata) => {
-            let file_size = metadata.len();
-
-            if needs_chunking(file_size) {
-                let file_name = Path::new(path)
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("audio.mp3");
-
-                info!(
-                    "File {} is {}MB, splitting into chunks",
-                    file_name,
-                    file_size / 1024 / 1024
-                );
+    method: SendMethod,
+    label: &str,
+    progress: Option<&UploadProgress<'_>>,
+    mut build_form: impl FnMut() -> F,
+) -> Option<Message>
+where
+    F: Future<Output = Result<multipart::Form, String>>,
+{
+    let mut attempt = 1;
+    loop {
+        if let Some(progress) = progress {
+            progress.sent_bytes.store(0, Ordering::Relaxed);
+        }
+        let form = match build_form().await {
+            Ok(form) => form,
+            Err(e) => {
+                error!("{}", e);
+                return None;
+            }
+        };
 
-                match split_mp3(path).await {
-                    Ok(chunks) => {
-                        let total_chunks = chunks.len();
-                        // Send each chunk
-                        for chunk in &chunks {
-                            let chunk_filename = chunk
-                                .path
-                                .file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or(file_name);
-
-                            info!(
-                                "Sending chunk: {} ({}MB)",
-                                chunk_filename,
-                                chunk.size / 1024 / 1024
-                            );
-
-                            // Add chunk info to title: "Song Title (Part 1/3)"
-                            let chunk_title =
-                                format!("{} (Part {}/{})", title, chunk.index, total_chunks);
-
-                            send_single_chunk(
-                                chat_id,
-                                chunk.path.to_str().unwrap(),
-                                performer,
-                                &chunk_title,
-                                bot_token,
-                                false, // Don't delete chunks here, cleanup_chunks() will handle it
-                            )
-                            .await;
-                        }
-
-                        // Clean up chunks
-                        if let Err(e) = cleanup_chunks(chunks).await {
-                            error!("Failed to clean up chunks: {}", e);
-                        }
-
-                        // Remove original file
-                        let _ = fs::remove_file(path).await;
-                    }
-                    Err(e) => {
-                        error!("Failed to split file {}: {}", file_name, e);
-                        // Fallback: try to send original file as-is
-                        send_single_chunk(chat_id, path, performer, title, bot_token, true).await;
-                    }
+        let started = Instant::now();
+        let result = tokio::select! {
+            result = telegram.send_media(method.api_method(), form) => result,
+            () = report_upload(telegram, chat_id, method, progress, started) => unreachable!(),
+        };
+        let error = match result {
+            Ok(message) => {
+                match progress {
+                    Some(progress) => info!(
+                        "Sent {} via {} ({} in {:.1}s)",
+                        label,
+                        method.api_method(),
+                        progress::format_bytes(progress.total_bytes),
+                        started.elapsed().as_secs_f64()
+                    ),
+                    None => info!(
+                        "File sent successfully to Telegram via {}.",
+                        method.api_method()
+                    ),
                 }
-            } else {
-                // File is under 50MB, send as-is
-                send_single_chunk(chat_id, path, performer, title, bot_token, true).await;
+                return Some(message);
             }
+            Err(e) => e,
+        };
+        match retry_delay(&error, attempt) {
+            Some(delay) if attempt < MAX_SEND_ATTEMPTS => {
+                warn!(
+                    "Sending {} failed (attempt {}/{}), retrying in {}s: {}",
+                    label,
+                    attempt,
+                    MAX_SEND_ATTEMPTS,
+                    delay.as_secs(),
+                    error
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            _ => {
+                error!(
+                    "Failed to send {} via {}: {}",
+                    label,
+                    method.api_method(),
+                    error
+                );
+                return None;
+            }
+        }
+    }
+}
+
+/// Sends one file. Returns the message if Telegram confirmed delivery; the
+/// file is left in place either way.
+async fn send_single_chunk(
+    upload: &Upload<'_>,
+    local_api: bool,
+    path: &str,
+    media: &MediaInfo<'_>,
+) -> Option<Message> {
+    let Upload {
+        telegram,
+        chat_id,
+        status,
+        label,
+    } = *upload;
+    status.update(&format!("{}...", label)).await;
+    let method = SendMethod::for_extension(extension_of(path));
+    // Probed once up front rather than on every attempt
+    let video = match method {
+        SendMethod::Video => Some(probe_video(path).await),
+        _ => None,
+    };
+    let mut media = *media;
+    if !method.has_duration_field() {
+        media.duration = None;
+    } else if media.duration.is_none() {
+        // Split parts get their own duration rather than the whole file's
+        match probe_duration(path).await {
+            Ok(duration) => media.duration = Some(duration),
+            Err(e) => warn!("Sending {} without duration: {}", path, e),
         }
+    }
+
+    // A local Bot API server reads the file from disk, so there is no
+    // upload to follow
+    let progress = match fs::metadata(path).await {
+        Ok(metadata) if !local_api => Some(UploadProgress {
+            status,
+            label,
+            total_bytes: metadata.len(),
+            sent_bytes: Arc::default(),
+        }),
+        _ => None,
+    };
+    let sent_bytes = progress
+        .as_ref()
+        .map_or_else(Arc::default, |progress| Arc::clone(&progress.sent_bytes));
+
+    let media = &media;
+    let video = video.as_ref();
+    let sent_bytes = &sent_bytes;
+    send_with_retry(telegram, chat_id, method, path, progress.as_ref(), || {
+        build_form(local_api, chat_id, path, method, media, video, sent_bytes)
+    })
+    .await
+}
+
+/// Sends previously delivered files again by their `file_id`, without
+/// uploading anything. Stops at the first file that does not arrive and
+/// returns how many were delivered before it, as those are already in the
+/// chat and must not be sent again.
+pub(crate) async fn resend(
+    telegram: &TelegramClient,
+    chat_id: i64,
+    files: &[SentFile],
+) -> Result<(), usize> {
+    for (delivered, file) in files.iter().enumerate() {
+        let build_form = || {
+            let form = multipart::Form::new()
+                .text("chat_id", chat_id.to_string())
+                .text(file.method.file_field(), file.file_id.clone())
+                .text("caption", file.caption.clone());
+            async { Ok(form) }
+        };
+        if send_with_retry(
+            telegram,
+            chat_id,
+            file.method,
+            &file.file_id,
+            None,
+            build_form,
+        )
+        .await
+        .is_none()
+        {
+            return Err(delivered);
+        }
+    }
+    Ok(())
+}
+
+/// Collects what Telegram stored for each part of a download.
+#[derive(Default)]
+struct Delivery {
+    files: Vec<SentFile>,
+    /// Set when a response did not name the stored file.
+    incomplete: bool,
+}
+
+impl Delivery {
+    fn record(&mut self, message: &Message, path: &str, media: &MediaInfo<'_>) {
+        match sent_file(message, path, media) {
+            Some(file) => self.files.push(file),
+            None => {
+                warn!("Telegram did not report a stored file for {}", path);
+                self.incomplete = true;
+            }
+        }
+    }
+
+    /// Empty unless every part can be sent again.
+    fn into_files(self) -> Vec<SentFile> {
+        if self.incomplete {
+            Vec::new()
+        } else {
+            self.files
+        }
+    }
+}
+
+async fn remove_delivered(path: &str) {
+    match fs::remove_file(path).await {
+        Ok(()) => info!("Deleted file: {}", path),
+        Err(e) => warn!("Failed to delete {}: {}", path, e),
+    }
+}
+
+/// Where a job's files go, and the status message reporting their upload.
+#[derive(Clone, Copy)]
+pub(crate) struct Upload<'a> {
+    pub(crate) telegram: &'a TelegramClient,
+    pub(crate) chat_id: i64,
+    pub(crate) status: &'a TelegramStatusMessage,
+    /// Names the upload on the status message, e.g. `Uploading part 2/3`.
+    pub(crate) label: &'a str,
+}
+
+/// Sends the file, split into parts if it is over the upload limit. Returns
+/// `None` unless every part was delivered; only then is the file deleted.
+/// The stored files are returned in order, or none if Telegram did not name
+/// all of them.
+pub async fn send_audio_to_telegram(
+    upload: &Upload<'_>,
+    config: &Config,
+    path: &str,
+    media: &MediaInfo<'_>,
+) -> Option<Vec<SentFile>> {
+    let split_config = config.split_config();
+    // Check file size and handle chunking transparently
+    let file_size = match fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
         Err(e) => {
             error!("Failed to get file metadata for {}: {}", path, e);
+            return None;
+        }
+    };
+
+    if !split_config.needs_chunking(file_size) {
+        // File is under the upload limit, send as-is
+        return send_whole(upload, config, path, media).await;
+    }
+
+    let file_name = Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("audio.mp3");
+
+    info!(
+        "File {} is {}MB, splitting into chunks",
+        file_name,
+        file_size / 1024 / 1024
+    );
+
+    upload.status.update("Splitting into parts...").await;
+    let chunks = match split_file(path, split_config).await {
+        Ok(chunks) => chunks,
+        Err(e) => {
+            error!("Failed to split file {}: {}", file_name, e);
+            // Fallback: try to send original file as-is
+            return send_whole(upload, config, path, media).await;
+        }
+    };
+
+    let total_chunks = chunks.len();
+    let mut delivered = true;
+    let mut delivery = Delivery::default();
+    for chunk in &chunks {
+        let chunk_filename = chunk
+            .path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(file_name);
+
+        info!(
+            "Sending chunk: {} ({}MB, from {:.0}s)",
+            chunk_filename,
+            chunk.size / 1024 / 1024,
+            chunk.start_secs.unwrap_or_default()
+        );
+
+        // Add chunk info to title: "Song Title (Part 1/3)"
+        let chunk_title = format!("{} (Part {}/{})", media.title, chunk.index, total_chunks);
+        let chunk_media = MediaInfo {
+            title: &chunk_title,
+            duration: chunk.duration_secs,
+            ..*media
+        };
+
+        // A missing part is reported, but the rest are still worth sending
+        let chunk_path = chunk.path.to_str().unwrap();
+        let chunk_label = format!("{} part {}/{}", upload.label, chunk.index, total_chunks);
+        let chunk_upload = Upload {
+            label: &chunk_label,
+            ..*upload
+        };
+        match send_single_chunk(&chunk_upload, config.local_api, chunk_path, &chunk_media).await {
+            Some(message) => delivery.record(&message, chunk_path, &chunk_media),
+            None => delivered = false,
         }
     }
+
+    // Parts can be cut again from the original, which is kept until all of
+    // them have arrived
+    if let Err(e) = cleanup_chunks(chunks).await {
+        error!("Failed to clean up chunks: {}", e);
+    }
+    if !delivered {
+        return None;
+    }
+    remove_delivered(path).await;
+    Some(delivery.into_files())
+}
+
+async fn send_whole(
+    upload: &Upload<'_>,
+    config: &Config,
+    path: &str,
+    media: &MediaInfo<'_>,
+) -> Option<Vec<SentFile>> {
+    let message = send_single_chunk(upload, config.local_api, path, media).await?;
+    remove_delivered(path).await;
+    let mut delivery = Delivery::default();
+    delivery.record(&message, path, media);
+    Some(delivery.into_files())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CountingReader, MediaInfo, SendMethod, SentFile, Upload, resend, retry_delay,
+        send_single_chunk,
+    };
+    use crate::telegram::{TelegramClient, TelegramError};
+    use crate::telegram_status::TelegramStatusMessage;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use tokio::io::AsyncReadExt;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const MEDIA: MediaInfo<'static> = MediaInfo {
+        performer: "Artist",
+        title: "Song",
+        caption: "flac",
+        thumbnail: None,
+        duration: None,
+    };
+
+    async fn mock_chat_action(server: &MockServer) {
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/sendChatAction"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "ok": true,
+                "result": true
+            })))
+            .mount(server)
+            .await;
+    }
+
+    #[test]
+    fn send_method_follows_extension() {
+        assert_eq!(SendMethod::for_extension("mp3"), SendMethod::Audio);
+        assert_eq!(SendMethod::for_extension("M4A"), SendMethod::Audio);
+        assert_eq!(SendMethod::for_extension("opus"), SendMethod::Voice);
+        assert_eq!(SendMethod::for_extension("mp4"), SendMethod::Video);
+        assert_eq!(SendMethod::for_extension("flac"), SendMethod::Document);
+        assert_eq!(SendMethod::for_extension("webm"), SendMethod::Document);
+    }
+
+    #[test]
+    fn retry_delay_follows_error_kind() {
+        let rate_limited = TelegramError::Api {
+            error_code: Some(429),
+            description: "Too Many Requests: retry after 7".to_string(),
+            retry_after: Some(7),
+        };
+        let rejected = TelegramError::Api {
+            error_code: Some(400),
+            description: "Bad Request: file is too big".to_string(),
+            retry_after: None,
+        };
+        let server_error = TelegramError::Api {
+            error_code: Some(502),
+            description: "Bad Gateway".to_string(),
+            retry_after: None,
+        };
+        let network = TelegramError::Transport("request failed: connection reset".to_string());
+
+        assert_eq!(retry_delay(&rate_limited, 3), Some(Duration::from_secs(7)));
+        assert_eq!(retry_delay(&rejected, 1), None);
+        assert_eq!(retry_delay(&server_error, 2), Some(Duration::from_secs(4)));
+        assert_eq!(retry_delay(&network, 1), Some(Duration::from_secs(2)));
+        assert_eq!(retry_delay(&network, 3), Some(Duration::from_secs(8)));
+    }
+
+    #[tokio::test]
+    async fn counting_reader_counts_every_byte_read() {
+        let read_bytes = Arc::default();
+        let mut reader = CountingReader {
+            inner: &b"fLaC-payload"[..],
+            read_bytes: Arc::clone(&read_bytes),
+        };
+
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).await.unwrap();
+
+        assert_eq!(body, b"fLaC-payload");
+        assert_eq!(read_bytes.load(Ordering::Relaxed), 12);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_upload_is_resent_with_the_whole_file() {
+        let server = MockServer::start().await;
+        mock_chat_action(&server).await;
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/sendDocument"))
+            .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+                "ok": false,
+                "description": "Too Many Requests: retry after 0",
+                "parameters": { "retry_after": 0 }
+            })))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/sendDocument"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "ok": true,
+                "result": { "message_id": 5 }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("song.flac");
+        std::fs::write(&file, b"fLaC-payload").unwrap();
+        let telegram = TelegramClient::new(&server.uri(), "TEST_TOKEN");
+        let status = TelegramStatusMessage::silent(&telegram, 7);
+        let upload = Upload {
+            telegram: &telegram,
+            chat_id: 7,
+            status: &status,
+            label: "Uploading",
+        };
+
+        let message = send_single_chunk(&upload, false, file.to_str().unwrap(), &MEDIA).await;
+
+        assert_eq!(message.unwrap().message_id, 5);
+        assert!(file.exists());
+        let uploads = server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|request| request.url.path() == "/botTEST_TOKEN/sendDocument")
+            .collect::<Vec<_>>();
+        assert_eq!(uploads.len(), 2);
+        for upload in uploads {
+            let body = String::from_utf8_lossy(&upload.body).to_string();
+            assert!(body.contains("fLaC-payload"));
+        }
+    }
+
+    #[tokio::test]
+    async fn server_error_is_retried_after_a_backoff() {
+        let server = MockServer::start().await;
+        mock_chat_action(&server).await;
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/sendDocument"))
+            .respond_with(ResponseTemplate::new(502).set_body_json(json!({
+                "ok": false,
+                "error_code": 502,
+                "description": "Bad Gateway"
+            })))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/sendDocument"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "ok": true,
+                "result": { "message_id": 8 }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("song.flac");
+        std::fs::write(&file, b"fLaC").unwrap();
+        let telegram = TelegramClient::new(&server.uri(), "TEST_TOKEN");
+        let status = TelegramStatusMessage::silent(&telegram, 7);
+        let upload = Upload {
+            telegram: &telegram,
+            chat_id: 7,
+            status: &status,
+            label: "Uploading",
+        };
+
+        let message = send_single_chunk(&upload, false, file.to_str().unwrap(), &MEDIA).await;
+
+        assert_eq!(message.unwrap().message_id, 8);
+    }
+
+    #[tokio::test]
+    async fn rejected_upload_is_not_retried() {
+        let server = MockServer::start().await;
+        mock_chat_action(&server).await;
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/sendDocument"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "ok": false,
+                "description": "Bad Request: file is too big"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("song.flac");
+        std::fs::write(&file, b"fLaC").unwrap();
+        let telegram = TelegramClient::new(&server.uri(), "TEST_TOKEN");
+        let status = TelegramStatusMessage::silent(&telegram, 7);
+        let upload = Upload {
+            telegram: &telegram,
+            chat_id: 7,
+            status: &status,
+            label: "Uploading",
+        };
+
+        let message = send_single_chunk(&upload, false, file.to_str().unwrap(), &MEDIA).await;
+
+        assert!(message.is_none());
+        assert!(file.exists());
+    }
+
+    #[tokio::test]
+    async fn cached_files_are_resent_by_file_id() {
+        let server = MockServer::start().await;
+        mock_chat_action(&server).await;
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/sendAudio"))
+            .and(body_string_contains("name=\"audio\"\r\n\r\nAUDIO_FILE_ID"))
+            .and(body_string_contains("mp3, standard 192k"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "ok": true,
+                "result": { "message_id": 6, "audio": { "file_id": "AUDIO_FILE_ID" } }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let telegram = TelegramClient::new(&server.uri(), "TEST_TOKEN");
+        let files = [SentFile {
+            method: SendMethod::Audio,
+            file_id: "AUDIO_FILE_ID".to_string(),
+            caption: "mp3, standard 192k".to_string(),
+        }];
+
+        assert_eq!(resend(&telegram, 7, &files).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn resend_reports_parts_delivered_before_a_refusal() {
+        let server = MockServer::start().await;
+        mock_chat_action(&server).await;
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/sendAudio"))
+            .and(body_string_contains("PART_1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "ok": true,
+                "result": { "message_id": 6, "audio": { "file_id": "PART_1" } }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/sendAudio"))
+            .and(body_string_contains("PART_2"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "ok": false,
+                "error_code": 400,
+                "description": "Bad Request: wrong file identifier"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let telegram = TelegramClient::new(&server.uri(), "TEST_TOKEN");
+        let files: Vec<SentFile> = (1..=3)
+            .map(|part| SentFile {
+                method: SendMethod::Audio,
+                file_id: format!("PART_{}", part),
+                caption: format!("part {}/3", part),
+            })
+            .collect();
+
+        // The third part is never tried once the second is refused
+        assert_eq!(resend(&telegram, 7, &files).await, Err(1));
+    }
 }
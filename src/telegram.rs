@@ -0,0 +1,474 @@
+use crate::types::InlineKeyboardMarkup;
+use reqwest::RequestBuilder;
+use reqwest::multipart::Form;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::time::Duration;
+
+/// Bot API client shared by every module. Clones share one connection pool.
+#[derive(Clone)]
+pub(crate) struct TelegramClient {
+    http: reqwest::Client,
+    api_base_url: String,
+    bot_token: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TelegramError {
+    /// The request was not answered with a Bot API response.
+    Transport(String),
+    /// Telegram answered with `ok: false`.
+    Api {
+        /// Mirrors the HTTP status, e.g. 400, 429 or 502.
+        error_code: Option<u16>,
+        description: String,
+        /// Seconds to wait before retrying, sent with `429 Too Many Requests`.
+        retry_after: Option<u64>,
+    },
+}
+
+impl fmt::Display for TelegramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TelegramError::Transport(message) => f.write_str(message),
+            TelegramError::Api { description, .. } => f.write_str(description),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    error_code: Option<u16>,
+    description: Option<String>,
+    parameters: Option<ResponseParameters>,
+}
+
+#[derive(Deserialize)]
+struct ResponseParameters {
+    retry_after: Option<u64>,
+}
+
+/// A message sent by the bot. The media fields are set for the kind of file
+/// Telegram stored, which can differ from the method used to send it.
+#[derive(Debug, Deserialize)]
+pub(crate) struct Message {
+    pub(crate) message_id: i64,
+    pub(crate) audio: Option<StoredFile>,
+    pub(crate) voice: Option<StoredFile>,
+    pub(crate) video: Option<StoredFile>,
+    pub(crate) document: Option<StoredFile>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct StoredFile {
+    /// Sends the same file again without uploading it.
+    pub(crate) file_id: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SendMessage<'a> {
+    pub(crate) chat_id: i64,
+    pub(crate) text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reply_markup: Option<&'a InlineKeyboardMarkup>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct EditMessageText<'a> {
+    pub(crate) chat_id: i64,
+    pub(crate) message_id: i64,
+    pub(crate) text: &'a str,
+    /// Omitting the markup removes any keyboard the message had.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reply_markup: Option<&'a InlineKeyboardMarkup>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct DeleteMessage {
+    pub(crate) chat_id: i64,
+    pub(crate) message_id: i64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct AnswerCallbackQuery<'a> {
+    pub(crate) callback_query_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) text: Option<&'a str>,
+}
+
+/// Upload activity shown in the chat header, e.g. "sending file...".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) enum ChatAction {
+    #[serde(rename = "upload_document")]
+    Document,
+    #[serde(rename = "upload_voice")]
+    Voice,
+    #[serde(rename = "upload_video")]
+    Video,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SendChatAction {
+    pub(crate) chat_id: i64,
+    pub(crate) action: ChatAction,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SetWebhook<'a> {
+    pub(crate) url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) secret_token: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct GetUpdates {
+    offset: Option<i64>,
+    timeout: u64,
+}
+
+impl TelegramClient {
+    pub(crate) fn new(api_base_url: &str, bot_token: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            api_base_url: api_base_url.trim_end_matches('/').to_string(),
+            bot_token: bot_token.to_string(),
+        }
+    }
+
+    pub(crate) async fn send_message(
+        &self,
+        request: &SendMessage<'_>,
+    ) -> Result<Message, TelegramError> {
+        self.post_json("sendMessage", request).await
+    }
+
+    pub(crate) async fn edit_message_text(
+        &self,
+        request: &EditMessageText<'_>,
+    ) -> Result<(), TelegramError> {
+        // The result is the edited message, or `true` for inline messages
+        self.post_json::<_, IgnoredAny>("editMessageText", request)
+            .await
+            .map(drop)
+    }
+
+    /// Returns whether Telegram confirmed the deletion.
+    pub(crate) async fn delete_message(
+        &self,
+        request: &DeleteMessage,
+    ) -> Result<bool, TelegramError> {
+        self.post_json("deleteMessage", request).await
+    }
+
+    pub(crate) async fn answer_callback_query(
+        &self,
+        request: &AnswerCallbackQuery<'_>,
+    ) -> Result<(), TelegramError> {
+        self.post_json::<_, bool>("answerCallbackQuery", request)
+            .await
+            .map(drop)
+    }
+
+    pub(crate) async fn send_chat_action(
+        &self,
+        request: &SendChatAction,
+    ) -> Result<(), TelegramError> {
+        self.post_json::<_, bool>("sendChatAction", request)
+            .await
+            .map(drop)
+    }
+
+    /// Uploads a file with `sendAudio`, `sendVoice`, `sendVideo` or
+    /// `sendDocument`, depending on `method`.
+    pub(crate) async fn send_media(
+        &self,
+        method: &str,
+        form: Form,
+    ) -> Result<Message, TelegramError> {
+        self.call(self.http.post(self.endpoint(method)).multipart(form))
+            .await
+    }
+
+    pub(crate) async fn set_webhook(&self, request: &SetWebhook<'_>) -> Result<(), TelegramError> {
+        self.post_json::<_, bool>("setWebhook", request)
+            .await
+            .map(drop)
+    }
+
+    pub(crate) async fn delete_webhook(&self) -> Result<(), TelegramError> {
+        self.call::<bool>(self.http.post(self.endpoint("deleteWebhook")))
+            .await
+            .map(drop)
+    }
+
+    /// Long-polls for updates, letting Telegram hold the request open for up
+    /// to `timeout_secs`. Updates are returned raw so one that fails to parse
+    /// can still be acknowledged.
+    pub(crate) async fn get_updates(
+        &self,
+        offset: Option<i64>,
+        timeout_secs: u64,
+    ) -> Result<Vec<Value>, TelegramError> {
+        let request = GetUpdates {
+            offset,
+            timeout: timeout_secs,
+        };
+        self.call(
+            self.http
+                .post(self.endpoint("getUpdates"))
+                .json(&request)
+                .timeout(Duration::from_secs(timeout_secs + 10)),
+        )
+        .await
+    }
+
+    async fn post_json<B: Serialize + ?Sized, R: DeserializeOwned>(
+        &self,
+        method: &str,
+        body: &B,
+    ) -> Result<R, TelegramError> {
+        self.call(self.http.post(self.endpoint(method)).json(body))
+            .await
+    }
+
+    async fn call<R: DeserializeOwned>(&self, request: RequestBuilder) -> Result<R, TelegramError> {
+        let response = request
+            .send()
+            .await
+            .map_err(|e| TelegramError::Transport(format!("request failed: {}", e)))?;
+
+        let status = response.status();
+        let body = response.json::<ApiResponse<R>>().await.map_err(|e| {
+            TelegramError::Transport(format!("HTTP {} with invalid body: {}", status, e))
+        })?;
+
+        if !body.ok {
+            return Err(TelegramError::Api {
+                error_code: body.error_code,
+                description: body
+                    .description
+                    .unwrap_or_else(|| "missing API description".to_string()),
+                retry_after: body.parameters.and_then(|p| p.retry_after),
+            });
+        }
+        body.result.ok_or_else(|| {
+            TelegramError::Transport("response did not include a result".to_string())
+        })
+    }
+
+    fn endpoint(&self, method: &str) -> String {
+        format!("{}/bot{}/{}", self.api_base_url, self.bot_token, method)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnswerCallbackQuery, ChatAction, SendChatAction, TelegramClient, TelegramError};
+    use reqwest::multipart::Form;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn client(server: &MockServer) -> TelegramClient {
+        TelegramClient::new(&server.uri(), "TEST_TOKEN")
+    }
+
+    #[tokio::test]
+    async fn get_updates_sends_offset_and_returns_results() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/getUpdates"))
+            .and(body_json(json!({
+                "offset": 101,
+                "timeout": 50
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "ok": true,
+                "result": [
+                    {
+                        "update_id": 101,
+                        "message": {
+                            "chat": { "id": 1 },
+                            "from": { "id": 1 },
+                            "text": "https://example.com"
+                        }
+                    },
+                    { "update_id": 102, "edited_message": {} }
+                ]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let updates = client(&server).get_updates(Some(101), 50).await.unwrap();
+
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[1]["update_id"], 102);
+    }
+
+    #[tokio::test]
+    async fn get_updates_reports_api_errors() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/getUpdates"))
+            .respond_with(ResponseTemplate::new(409).set_body_json(json!({
+                "ok": false,
+                "description": "Conflict: can't use getUpdates method while webhook is active"
+            })))
+            .mount(&server)
+            .await;
+
+        let result = client(&server).get_updates(None, 50).await;
+
+        assert_eq!(
+            result,
+            Err(TelegramError::Api {
+                error_code: None,
+                description: "Conflict: can't use getUpdates method while webhook is active"
+                    .to_string(),
+                retry_after: None,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn answer_posts_callback_query_id_and_text() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/answerCallbackQuery"))
+            .and(body_json(json!({
+                "callback_query_id": "abc",
+                "text": "Too late"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "ok": true,
+                "result": true
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client(&server)
+            .answer_callback_query(&AnswerCallbackQuery {
+                callback_query_id: "abc",
+                text: Some("Too late"),
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn answer_reports_api_errors() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/answerCallbackQuery"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "ok": false,
+                "description": "query is too old"
+            })))
+            .mount(&server)
+            .await;
+
+        let error = client(&server)
+            .answer_callback_query(&AnswerCallbackQuery {
+                callback_query_id: "abc",
+                text: None,
+            })
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), "query is too old");
+    }
+
+    #[tokio::test]
+    async fn rate_limit_carries_retry_after() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/sendChatAction"))
+            .and(body_json(json!({
+                "chat_id": 7,
+                "action": "upload_voice"
+            })))
+            .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+                "ok": false,
+                "error_code": 429,
+                "description": "Too Many Requests: retry after 7",
+                "parameters": { "retry_after": 7 }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let error = client(&server)
+            .send_chat_action(&SendChatAction {
+                chat_id: 7,
+                action: ChatAction::Voice,
+            })
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error,
+            TelegramError::Api {
+                error_code: Some(429),
+                description: "Too Many Requests: retry after 7".to_string(),
+                retry_after: Some(7),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn send_media_posts_multipart_and_returns_message() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/sendDocument"))
+            .and(body_string_contains("name=\"chat_id\""))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "ok": true,
+                "result": { "message_id": 99, "document": { "file_id": "abc" } }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let form = Form::new().text("chat_id", "7");
+        let message = client(&server)
+            .send_media("sendDocument", form)
+            .await
+            .unwrap();
+
+        assert_eq!(message.message_id, 99);
+        assert_eq!(message.document.unwrap().file_id, "abc");
+        assert!(message.audio.is_none());
+    }
+
+    #[tokio::test]
+    async fn trailing_slash_in_base_url_is_ignored() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/deleteWebhook"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "ok": true,
+                "result": true
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        TelegramClient::new(&format!("{}/", server.uri()), "TEST_TOKEN")
+            .delete_webhook()
+            .await
+            .unwrap();
+    }
+}
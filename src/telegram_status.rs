@@ -1,206 +1,148 @@
-use log::{error, warn};
+use crate::telegram::{DeleteMessage, EditMessageText, SendMessage, TelegramClient};
+use crate::types::InlineKeyboardMarkup;
+use log::warn;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-const TELEGRAM_API_BASE_URL: &str = "https://api.telegram.org";
+/// Minimum time between progress edits, well within Telegram's limit on how
+/// often a message may be edited.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(3);
 
 pub(crate) struct TelegramStatusMessage {
-    client: reqwest::Client,
-    api_base_url: String,
-    bot_token: String,
+    telegram: TelegramClient,
     chat_id: i64,
     message_id: Option<i64>,
+    last_edit: Mutex<Option<LastEdit>>,
 }
 
-#[derive(serde::Serialize)]
-struct SendMessageRequest<'a> {
-    chat_id: i64,
-    text: &'a str,
-}
-
-#[derive(serde::Serialize)]
-struct EditMessageTextRequest<'a> {
-    chat_id: i64,
-    message_id: i64,
-    text: &'a str,
-}
-
-#[derive(serde::Serialize)]
-struct DeleteMessageRequest {
-    chat_id: i64,
-    message_id: i64,
-}
-
-#[derive(serde::Deserialize)]
-struct TelegramApiResponse<T> {
-    ok: bool,
-    result: Option<T>,
-    description: Option<String>,
-}
-
-#[derive(serde::Deserialize)]
-struct SentMessage {
-    message_id: i64,
+struct LastEdit {
+    at: Instant,
+    text: String,
+    with_keyboard: bool,
 }
 
 impl TelegramStatusMessage {
-    pub(crate) async fn create(chat_id: i64, bot_token: &str, initial_text: &str) -> Self {
-        Self::create_with_client_and_base_url(
-            reqwest::Client::new(),
-            TELEGRAM_API_BASE_URL,
-            chat_id,
-            bot_token,
-            initial_text,
-        )
-        .await
-    }
-
-    #[cfg(test)]
-    async fn create_with_base_url(
-        api_base_url: &str,
-        chat_id: i64,
-        bot_token: &str,
-        initial_text: &str,
-    ) -> Self {
-        Self::create_with_client_and_base_url(
-            reqwest::Client::new(),
-            api_base_url,
-            chat_id,
-            bot_token,
-            initial_text,
-        )
-        .await
-    }
-
-    async fn create_with_client_and_base_url(
-        client: reqwest::Client,
-        api_base_url: &str,
+    pub(crate) async fn create(
+        telegram: &TelegramClient,
         chat_id: i64,
-        bot_token: &str,
         initial_text: &str,
     ) -> Self {
         let mut status = Self {
-            client,
-            api_base_url: api_base_url.trim_end_matches('/').to_string(),
-            bot_token: bot_token.to_string(),
+            telegram: telegram.clone(),
             chat_id,
             message_id: None,
+            last_edit: Mutex::new(None),
         };
 
-        let request = SendMessageRequest {
+        let request = SendMessage {
             chat_id,
             text: initial_text,
+            reply_markup: None,
         };
+        match telegram.send_message(&request).await {
+            Ok(message) => status.message_id = Some(message.message_id),
+            Err(e) => warn!("Failed to create Telegram status message: {}", e),
+        }
+        status
+    }
 
-        let response = match status
-            .client
-            .post(status.endpoint("sendMessage"))
-            .json(&request)
-            .send()
-            .await
-        {
-            Ok(response) => response,
-            Err(error) => {
-                error!("Failed to create Telegram status message: {}", error);
-                return status;
-            }
+    /// Reuses a status message created before a restart, or creates a new one
+    /// if the job never got that far.
+    pub(crate) async fn resume(
+        telegram: &TelegramClient,
+        chat_id: i64,
+        message_id: Option<i64>,
+        text: &str,
+    ) -> Self {
+        let Some(message_id) = message_id else {
+            return Self::create(telegram, chat_id, text).await;
         };
 
-        if !response.status().is_success() {
-            warn!(
-                "Telegram status message creation returned HTTP status {}",
-                response.status()
-            );
-            return status;
+        let status = Self::attach(telegram, chat_id, message_id);
+        status.update(text).await;
+        status
+    }
+
+    /// A handle without a message, whose updates are dropped. Used for jobs
+    /// that report progress elsewhere, such as playlist items.
+    pub(crate) fn silent(telegram: &TelegramClient, chat_id: i64) -> Self {
+        Self {
+            telegram: telegram.clone(),
+            chat_id,
+            message_id: None,
+            last_edit: Mutex::new(None),
         }
+    }
 
-        let body = match response.json::<TelegramApiResponse<SentMessage>>().await {
-            Ok(body) => body,
-            Err(error) => {
-                error!(
-                    "Failed to decode Telegram status message response: {}",
-                    error
-                );
-                return status;
-            }
-        };
+    pub(crate) fn message_id(&self) -> Option<i64> {
+        self.message_id
+    }
 
-        if !body.ok {
-            warn!(
-                "Telegram status message creation failed: {}",
-                body.description
-                    .as_deref()
-                    .unwrap_or("missing API description")
-            );
-            return status;
+    /// A handle for an existing message, without editing it.
+    pub(crate) fn attach(telegram: &TelegramClient, chat_id: i64, message_id: i64) -> Self {
+        Self {
+            telegram: telegram.clone(),
+            chat_id,
+            message_id: Some(message_id),
+            last_edit: Mutex::new(None),
         }
+    }
 
-        let Some(sent_message) = body.result else {
-            warn!("Telegram status message creation response did not include a result");
-            return status;
-        };
+    pub(crate) async fn update(&self, text: &str) {
+        self.edit(text, None).await;
+    }
 
-        status.message_id = Some(sent_message.message_id);
-        status
+    /// Like `update`, but dropped if the text changed less than
+    /// `PROGRESS_INTERVAL` ago. Meant for frequent updates such as download
+    /// percentages, of which only the latest matters.
+    pub(crate) async fn progress(&self, text: &str) {
+        let recently_edited = self
+            .last_edit
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|last| last.at.elapsed() < PROGRESS_INTERVAL);
+        if !recently_edited {
+            self.update(text).await;
+        }
     }
 
-    pub(crate) async fn update(&self, text: &str) {
+    /// Replaces the text and attaches an inline keyboard below it.
+    pub(crate) async fn update_with_keyboard(&self, text: &str, keyboard: &InlineKeyboardMarkup) {
+        self.edit(text, Some(keyboard)).await;
+    }
+
+    async fn edit(&self, text: &str, reply_markup: Option<&InlineKeyboardMarkup>) {
         let Some(message_id) = self.message_id else {
             return;
         };
+        // Telegram rejects an edit that changes nothing
+        let unchanged = reply_markup.is_none()
+            && self
+                .last_edit
+                .lock()
+                .unwrap()
+                .as_ref()
+                .is_some_and(|last| !last.with_keyboard && last.text == text);
+        if unchanged {
+            return;
+        }
 
-        let request = EditMessageTextRequest {
+        let request = EditMessageText {
             chat_id: self.chat_id,
             message_id,
             text,
+            reply_markup,
         };
-
-        let response = match self
-            .client
-            .post(self.endpoint("editMessageText"))
-            .json(&request)
-            .send()
-            .await
-        {
-            Ok(response) => response,
-            Err(error) => {
-                error!("Failed to update Telegram status message: {}", error);
-                return;
-            }
-        };
-
-        if !response.status().is_success() {
-            warn!(
-                "Telegram status message update returned HTTP status {}",
-                response.status()
-            );
-            return;
-        }
-
-        let body = match response
-            .json::<TelegramApiResponse<serde_json::Value>>()
-            .await
-        {
-            Ok(body) => body,
-            Err(error) => {
-                error!(
-                    "Failed to decode Telegram status update response: {}",
-                    error
-                );
-                return;
+        match self.telegram.edit_message_text(&request).await {
+            Ok(()) => {
+                *self.last_edit.lock().unwrap() = Some(LastEdit {
+                    at: Instant::now(),
+                    text: text.to_string(),
+                    with_keyboard: reply_markup.is_some(),
+                })
             }
-        };
-
-        if !body.ok {
-            warn!(
-                "Telegram status message update failed: {}",
-                body.description
-                    .as_deref()
-                    .unwrap_or("missing API description")
-            );
-            return;
-        }
-
-        if body.result.is_none() {
-            warn!("Telegram status message update response did not include a result");
+            Err(e) => warn!("Failed to update Telegram status message: {}", e),
         }
     }
 
@@ -209,67 +151,25 @@ impl TelegramStatusMessage {
             return;
         };
 
-        let request = DeleteMessageRequest {
+        let request = DeleteMessage {
             chat_id: self.chat_id,
             message_id,
         };
-
-        let response = match self
-            .client
-            .post(self.endpoint("deleteMessage"))
-            .json(&request)
-            .send()
-            .await
-        {
-            Ok(response) => response,
-            Err(error) => {
-                error!("Failed to delete Telegram status message: {}", error);
-                return;
+        match self.telegram.delete_message(&request).await {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("Telegram status message deletion response did not confirm deletion")
             }
-        };
-
-        if !response.status().is_success() {
-            warn!(
-                "Telegram status message deletion returned HTTP status {}",
-                response.status()
-            );
-            return;
+            Err(e) => warn!("Failed to delete Telegram status message: {}", e),
         }
-
-        let body = match response.json::<TelegramApiResponse<bool>>().await {
-            Ok(body) => body,
-            Err(error) => {
-                error!(
-                    "Failed to decode Telegram status deletion response: {}",
-                    error
-                );
-                return;
-            }
-        };
-
-        if !body.ok {
-            warn!(
-                "Telegram status message deletion failed: {}",
-                body.description
-                    .as_deref()
-                    .unwrap_or("missing API description")
-            );
-            return;
-        }
-
-        if body.result != Some(true) {
-            warn!("Telegram status message deletion response did not confirm deletion");
-        }
-    }
-
-    fn endpoint(&self, method: &str) -> String {
-        format!("{}/bot{}/{}", self.api_base_url, self.bot_token, method)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::TelegramStatusMessage;
+    use crate::telegram::TelegramClient;
+    use crate::types::{InlineKeyboardButton, InlineKeyboardMarkup};
     use serde_json::json;
     use wiremock::matchers::{body_json, method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
@@ -302,9 +202,12 @@ mod tests {
         }))
     }
 
+    fn client(server: &MockServer) -> TelegramClient {
+        TelegramClient::new(&server.uri(), TOKEN)
+    }
+
     async fn create_status(server: &MockServer) -> TelegramStatusMessage {
-        TelegramStatusMessage::create_with_base_url(&server.uri(), CHAT_ID, TOKEN, "Starting...")
-            .await
+        TelegramStatusMessage::create(&client(server), CHAT_ID, "Starting...").await
     }
 
     #[tokio::test]
@@ -392,6 +295,47 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn progress_edits_are_throttled_but_stages_are_not() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/sendMessage"))
+            .respond_with(successful_message(42))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/editMessageText"))
+            .respond_with(successful_edit())
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let status = create_status(&server).await;
+        status.progress("Downloading 10% of 4.0 MB").await;
+        status.progress("Downloading 20% of 4.0 MB").await;
+        status.update("Converting with ffmpeg...").await;
+        // Unchanged text is not sent again
+        status.update("Converting with ffmpeg...").await;
+
+        let texts = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|request| request.url.path() == "/botTEST_TOKEN/editMessageText")
+            .map(|request| {
+                let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+                body["text"].as_str().unwrap().to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec!["Downloading 10% of 4.0 MB", "Converting with ffmpeg..."]
+        );
+    }
+
     #[tokio::test]
     async fn creation_http_failure_disables_later_updates() {
         let server = MockServer::start().await;
@@ -601,6 +545,66 @@ mod tests {
         status.delete().await;
     }
 
+    #[tokio::test]
+    async fn attached_handle_edits_existing_message() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/editMessageText"))
+            .and(body_json(json!({
+                "chat_id": CHAT_ID,
+                "message_id": 42,
+                "text": "Resuming after restart..."
+            })))
+            .respond_with(successful_edit())
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let status = TelegramStatusMessage::attach(&client(&server), CHAT_ID, 42);
+        status.update("Resuming after restart...").await;
+
+        assert_eq!(status.message_id(), Some(42));
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn keyboard_is_attached_to_edited_message() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/sendMessage"))
+            .respond_with(successful_message(42))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/editMessageText"))
+            .and(body_json(json!({
+                "chat_id": CHAT_ID,
+                "message_id": 42,
+                "text": "Choose a format",
+                "reply_markup": {
+                    "inline_keyboard": [[{ "text": "Cancel", "callback_data": "1:cancel" }]]
+                }
+            })))
+            .respond_with(successful_edit())
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let keyboard = InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![InlineKeyboardButton {
+                text: "Cancel".to_string(),
+                callback_data: "1:cancel".to_string(),
+            }]],
+        };
+        let status = create_status(&server).await;
+        status
+            .update_with_keyboard("Choose a format", &keyboard)
+            .await;
+    }
+
     #[tokio::test]
     async fn independent_handles_use_their_own_message_ids() {
         let server = MockServer::start().await;
@@ -632,12 +636,8 @@ mod tests {
             .mount(&server)
             .await;
 
-        let first =
-            TelegramStatusMessage::create_with_base_url(&server.uri(), CHAT_ID, TOKEN, "First")
-                .await;
-        let second =
-            TelegramStatusMessage::create_with_base_url(&server.uri(), CHAT_ID, TOKEN, "Second")
-                .await;
+        let first = TelegramStatusMessage::create(&client(&server), CHAT_ID, "First").await;
+        let second = TelegramStatusMessage::create(&client(&server), CHAT_ID, "Second").await;
 
         first.update("Download completed").await;
         second.update("Download failed").await;
@@ -0,0 +1,127 @@
+use log::warn;
+use serde_json::Value;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+const THUMBNAIL_MAX_SIDE: u32 = 320;
+const THUMBNAIL_MAX_BYTES: usize = 200 * 1024;
+
+/// Thumbnail URL from `yt-dlp -j` output.
+pub(crate) fn thumbnail_url(metadata: &Value) -> Option<&str> {
+    metadata.get("thumbnail").and_then(Value::as_str)
+}
+
+fn is_jpeg(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0xFF, 0xD8, 0xFF])
+}
+
+/// Downloads the thumbnail and returns it as JPEG. Other formats, such as
+/// the WebP thumbnails YouTube serves, are converted with `ffmpeg`.
+pub(crate) async fn fetch_jpeg(url: &str) -> Option<Vec<u8>> {
+    let bytes = match reqwest::get(url).await.and_then(|r| r.error_for_status()) {
+        Ok(response) => match response.bytes().await {
+            Ok(bytes) => bytes.to_vec(),
+            Err(e) => {
+                warn!("Failed to read thumbnail {}: {}", url, e);
+                return None;
+            }
+        },
+        Err(e) => {
+            warn!("Failed to download thumbnail {}: {}", url, e);
+            return None;
+        }
+    };
+
+    if is_jpeg(&bytes) {
+        return Some(bytes);
+    }
+    match to_jpeg(&bytes, None).await {
+        Ok(jpeg) => Some(jpeg),
+        Err(e) => {
+            warn!("Failed to convert thumbnail {}: {}", url, e);
+            None
+        }
+    }
+}
+
+/// Scales a cover down to Telegram's thumbnail rules: JPEG, at most 320px
+/// on each side and under 200 kB. `None` if ffmpeg fails or the result is
+/// still too large, in which case the file is sent without a thumbnail.
+pub(crate) async fn telegram_thumbnail(cover: &[u8]) -> Option<Vec<u8>> {
+    let scale = format!(
+        "scale={0}:{0}:force_original_aspect_ratio=decrease",
+        THUMBNAIL_MAX_SIDE
+    );
+    match to_jpeg(cover, Some(&scale)).await {
+        Ok(thumbnail) if thumbnail.len() < THUMBNAIL_MAX_BYTES => Some(thumbnail),
+        Ok(thumbnail) => {
+            warn!("Thumbnail is {} bytes, sending without it", thumbnail.len());
+            None
+        }
+        Err(e) => {
+            warn!("Failed to scale thumbnail: {}", e);
+            None
+        }
+    }
+}
+
+async fn to_jpeg(image: &[u8], filter: Option<&str>) -> Result<Vec<u8>, String> {
+    let mut command = Command::new("ffmpeg");
+    command.args(["-v", "error", "-i", "pipe:0"]);
+    if let Some(filter) = filter {
+        command.args(["-vf", filter]);
+    }
+    let mut child = command
+        .args(["-frames:v", "1", "-f", "image2", "-c:v", "mjpeg", "pipe:1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("failed to run ffmpeg: {}", e))?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let image = image.to_vec();
+    // Feed stdin concurrently so a full stdout pipe cannot deadlock ffmpeg
+    let writer = tokio::spawn(async move {
+        let _ = stdin.write_all(&image).await;
+    });
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(|e| format!("ffmpeg failed: {}", e))?;
+    let _ = writer.await;
+
+    if !output.status.success() || !is_jpeg(&output.stdout) {
+        return Err(format!(
+            "ffmpeg exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_jpeg, thumbnail_url};
+    use serde_json::json;
+
+    #[test]
+    fn jpeg_is_detected_by_magic_bytes() {
+        assert!(is_jpeg(&[0xFF, 0xD8, 0xFF, 0xE0]));
+        assert!(!is_jpeg(b"RIFF\0\0\0\0WEBP"));
+    }
+
+    #[test]
+    fn thumbnail_url_is_read_from_metadata() {
+        let metadata = json!({ "thumbnail": "https://i.ytimg.com/vi/x/maxresdefault.webp" });
+
+        assert_eq!(
+            thumbnail_url(&metadata),
+            Some("https://i.ytimg.com/vi/x/maxresdefault.webp")
+        );
+        assert_eq!(thumbnail_url(&json!({})), None);
+    }
+}
@@ -1,8 +1,12 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize)]
 pub struct TelegramWebhook {
-    pub message: TelegramMessage,
+    pub update_id: i64,
+    /// Absent for update kinds the service does not handle, such as edited messages.
+    pub message: Option<TelegramMessage>,
+    /// Sent when a user taps a button of an inline keyboard.
+    pub callback_query: Option<CallbackQuery>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -10,6 +14,19 @@ pub struct TelegramMessage {
     pub chat: TelegramChat,
     pub from: TelegramFrom,
     pub text: Option<String>,
+    /// Special entities in `text`, such as bot commands and URLs.
+    #[serde(default)]
+    pub entities: Vec<MessageEntity>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessageEntity {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Offset in UTF-16 code units.
+    pub offset: usize,
+    /// Length in UTF-16 code units.
+    pub length: usize,
 }
 
 #[derive(Debug, Deserialize)]
@@ -21,3 +38,28 @@ pub struct TelegramChat {
 pub struct TelegramFrom {
     pub id: i64,
 }
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    pub id: String,
+    pub from: TelegramFrom,
+    /// The message carrying the keyboard; absent if it is too old.
+    pub message: Option<CallbackMessage>,
+    pub data: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackMessage {
+    pub chat: TelegramChat,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InlineKeyboardMarkup {
+    pub inline_keyboard: Vec<Vec<InlineKeyboardButton>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InlineKeyboardButton {
+    pub text: String,
+    pub callback_data: String,
+}
@@ -0,0 +1,126 @@
+use crate::AppState;
+use crate::commands;
+use crate::download_options;
+use crate::format_choice;
+use crate::jobs::JobState;
+use crate::playlist;
+use crate::telegram_status::TelegramStatusMessage;
+use crate::types::TelegramWebhook;
+use crate::worker_pool;
+use log::{debug, error, info, warn};
+
+/// Handles one update, whether it arrived through the webhook or long polling.
+pub(crate) async fn handle_update(state: &AppState, update: TelegramWebhook) {
+    if let Some(query) = update.callback_query {
+        let user_id = query.from.id;
+        let chat_id = query
+            .message
+            .as_ref()
+            .map_or(user_id, |message| message.chat.id);
+        if !state.access.is_allowed(user_id, chat_id) {
+            warn!(
+                "Unauthorized callback from user {} in chat {}",
+                user_id, chat_id
+            );
+            return;
+        }
+
+        if query
+            .data
+            .as_deref()
+            .is_some_and(playlist::is_playlist_callback)
+        {
+            playlist::handle_callback(state, query).await;
+        } else {
+            format_choice::handle_callback(state, query).await;
+        }
+        return;
+    }
+    let Some(message) = update.message else {
+        debug!("Ignoring update {} without a message", update.update_id);
+        return;
+    };
+    let user_id = message.from.id;
+    let chat_id = message.chat.id;
+
+    // Check if the message is from an allowed user or chat
+    if !state.access.is_allowed(user_id, chat_id) {
+        warn!("Unauthorized user {} in chat {}", user_id, chat_id);
+        return;
+    }
+
+    if let Some(command) = commands::parse_command(&message) {
+        commands::handle_command(state, user_id, chat_id, command).await;
+        return;
+    }
+
+    let Some(text) = message.text else {
+        return;
+    };
+
+    let request = match download_options::parse_request(&text) {
+        Ok(request) => request,
+        Err(reply) => {
+            TelegramStatusMessage::create(&state.telegram, chat_id, &reply).await;
+            return;
+        }
+    };
+    let options = request.resolve(state.preferences.get(user_id));
+
+    if request.playlist {
+        info!("Received playlist request for URL: {}", request.url);
+        // Enumerating the playlist runs yt-dlp, so do not hold up the update loop
+        tokio::spawn(playlist::offer(
+            state.clone(),
+            chat_id,
+            user_id,
+            request.url,
+            options,
+        ));
+        return;
+    }
+
+    info!(
+        "Received download request for URL: {} ({})",
+        request.url,
+        options.describe()
+    );
+
+    // Without explicit options the user picks a format from a keyboard first
+    let initial_state = if request.has_options() {
+        JobState::Queued
+    } else {
+        JobState::AwaitingChoice
+    };
+
+    // Persist the job before acknowledging the update so a restart cannot lose it
+    let job = match state
+        .jobs
+        .create(chat_id, user_id, &request.url, options, initial_state)
+    {
+        Ok(job) => job,
+        Err(e) => {
+            error!("Failed to record job for {}: {}", request.url, e);
+            return;
+        }
+    };
+
+    if job.state == JobState::AwaitingChoice {
+        // Looking up formats runs yt-dlp, so do not hold up the update loop
+        tokio::spawn(format_choice::offer_choices(state.clone(), job));
+        return;
+    }
+
+    let status = TelegramStatusMessage::create(
+        &state.telegram,
+        job.chat_id,
+        &worker_pool::queued_text(state.pool.queued_len() + 1),
+    )
+    .await;
+    let job = state
+        .jobs
+        .update(job.id, |job| job.status_message_id = status.message_id())
+        .unwrap_or(job);
+
+    state.pool.enqueue(job);
+}
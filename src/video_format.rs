@@ -0,0 +1,237 @@
+use log::warn;
+use serde_json::Value;
+use tokio::process::Command;
+
+/// Height used when a request asks for `video` without naming one.
+pub(crate) const DEFAULT_VIDEO_HEIGHT: u32 = 720;
+
+/// Size a selected format may have. `filesize_approx` is only an estimate, so
+/// leave headroom below the upload limit.
+pub(crate) fn size_budget(upload_limit: u64) -> u64 {
+    upload_limit / 10 * 9
+}
+
+/// A yt-dlp format choice whose estimated size fits the upload limit.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct VideoSelection {
+    /// Value for `-f`, e.g. `137+140` or `18`.
+    pub(crate) format_id: String,
+    pub(crate) height: u32,
+    pub(crate) estimated_size: u64,
+}
+
+/// Stream properties sent along with `sendVideo`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct VideoInfo {
+    pub(crate) width: Option<u64>,
+    pub(crate) height: Option<u64>,
+    pub(crate) duration: Option<u64>,
+}
+
+/// Best video no taller than `height` with the best audio, falling back to
+/// the best single file when the site offers no separate streams. Used when
+/// no format is known to fit the upload limit; oversized results are split.
+pub(crate) fn fallback_selector(height: u32) -> String {
+    format!("bv*[height<={h}]+ba/b[height<={h}]/b", h = height)
+}
+
+/// Picks the tallest format no taller than `max_height` whose size, from the
+/// `filesize`/`filesize_approx` fields of `yt-dlp -j` output, fits in
+/// `size_limit`. Video-only formats are paired with the best audio-only
+/// format. Formats without a size estimate are skipped.
+pub(crate) fn select_format(
+    metadata: &Value,
+    max_height: u32,
+    size_limit: u64,
+) -> Option<VideoSelection> {
+    let formats = metadata.get("formats").and_then(Value::as_array)?;
+
+    let audio = formats
+        .iter()
+        .filter(|format| has_stream(format, "acodec") && !has_stream(format, "vcodec"))
+        .filter_map(|format| Some((format, estimated_size(format)?)))
+        .max_by(|(a, a_size), (b, b_size)| {
+            // Prefer audio that merges into MP4 without re-encoding, then bitrate
+            is_m4a(a)
+                .cmp(&is_m4a(b))
+                .then(bitrate(a).total_cmp(&bitrate(b)))
+                .then(a_size.cmp(b_size))
+        });
+
+    formats
+        .iter()
+        .filter(|format| has_stream(format, "vcodec"))
+        .filter_map(|format| {
+            let height = u32::try_from(format.get("height")?.as_u64()?).ok()?;
+            if height > max_height {
+                return None;
+            }
+            let id = format.get("format_id")?.as_str()?;
+            let video_size = estimated_size(format)?;
+
+            let (format_id, size) = if has_stream(format, "acodec") {
+                (id.to_string(), video_size)
+            } else {
+                let (audio, audio_size) = audio?;
+                let audio_id = audio.get("format_id")?.as_str()?;
+                (format!("{}+{}", id, audio_id), video_size + audio_size)
+            };
+            (size <= size_limit).then_some(VideoSelection {
+                format_id,
+                height,
+                estimated_size: size,
+            })
+        })
+        .max_by_key(|selection| (selection.height, selection.estimated_size))
+}
+
+fn has_stream(format: &Value, codec_field: &str) -> bool {
+    format
+        .get(codec_field)
+        .and_then(Value::as_str)
+        .is_some_and(|codec| codec != "none")
+}
+
+fn estimated_size(format: &Value) -> Option<u64> {
+    format
+        .get("filesize")
+        .and_then(Value::as_u64)
+        .or_else(|| format.get("filesize_approx").and_then(Value::as_u64))
+}
+
+fn is_m4a(format: &Value) -> bool {
+    format.get("ext").and_then(Value::as_str) == Some("m4a")
+}
+
+fn bitrate(format: &Value) -> f64 {
+    format.get("abr").and_then(Value::as_f64).unwrap_or(0.0)
+}
+
+/// Reads the first video stream's dimensions and the duration with `ffprobe`.
+/// Missing values are left out of the upload rather than failing it.
+pub(crate) async fn probe_video(path: &str) -> VideoInfo {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=width,height:format=duration"])
+        .args(["-of", "json"])
+        .arg(path)
+        .output()
+        .await;
+
+    match output {
+        Ok(output) if output.status.success() => parse_probe_output(&output.stdout),
+        Ok(output) => {
+            warn!(
+                "ffprobe failed for {}: {}",
+                path,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            VideoInfo::default()
+        }
+        Err(e) => {
+            warn!("Failed to run ffprobe for {}: {}", path, e);
+            VideoInfo::default()
+        }
+    }
+}
+
+fn parse_probe_output(stdout: &[u8]) -> VideoInfo {
+    let Ok(probe) = serde_json::from_slice::<Value>(stdout) else {
+        return VideoInfo::default();
+    };
+    let stream = probe.get("streams").and_then(|streams| streams.get(0));
+    VideoInfo {
+        width: stream.and_then(|s| s.get("width")).and_then(Value::as_u64),
+        height: stream.and_then(|s| s.get("height")).and_then(Value::as_u64),
+        // ffprobe prints the duration as a string, e.g. "212.024000"
+        duration: probe
+            .get("format")
+            .and_then(|f| f.get("duration"))
+            .and_then(Value::as_str)
+            .and_then(|d| d.parse::<f64>().ok())
+            .map(|d| d.round() as u64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VideoInfo, VideoSelection, fallback_selector, parse_probe_output, select_format};
+    use serde_json::json;
+
+    const MB: u64 = 1024 * 1024;
+
+    fn metadata() -> serde_json::Value {
+        json!({
+            "formats": [
+                { "format_id": "140", "ext": "m4a", "acodec": "mp4a", "vcodec": "none", "abr": 129.0, "filesize": 3 * MB },
+                { "format_id": "251", "ext": "webm", "acodec": "opus", "vcodec": "none", "abr": 135.0, "filesize": 3 * MB },
+                { "format_id": "18", "ext": "mp4", "acodec": "mp4a", "vcodec": "avc1", "height": 360, "filesize_approx": 20 * MB },
+                { "format_id": "136", "ext": "mp4", "acodec": "none", "vcodec": "avc1", "height": 720, "filesize": 45 * MB },
+                { "format_id": "135", "ext": "mp4", "acodec": "none", "vcodec": "avc1", "height": 480, "filesize_approx": 25 * MB },
+                { "format_id": "137", "ext": "mp4", "acodec": "none", "vcodec": "avc1", "height": 1080, "filesize": 90 * MB },
+                { "format_id": "sb0", "ext": "mhtml", "acodec": "none", "vcodec": "none" }
+            ]
+        })
+    }
+
+    #[test]
+    fn tallest_format_that_fits_is_chosen() {
+        let selection = select_format(&metadata(), 1080, 50 * MB).unwrap();
+
+        assert_eq!(
+            selection,
+            VideoSelection {
+                format_id: "136+140".to_string(),
+                height: 720,
+                estimated_size: 48 * MB,
+            }
+        );
+    }
+
+    #[test]
+    fn height_cap_is_respected() {
+        let selection = select_format(&metadata(), 480, 50 * MB).unwrap();
+
+        assert_eq!(selection.format_id, "135+140");
+    }
+
+    #[test]
+    fn combined_formats_need_no_audio() {
+        let selection = select_format(&metadata(), 360, 50 * MB).unwrap();
+
+        assert_eq!(selection.format_id, "18");
+        assert_eq!(selection.estimated_size, 20 * MB);
+    }
+
+    #[test]
+    fn nothing_fits_a_tiny_limit() {
+        assert_eq!(select_format(&metadata(), 1080, 10 * MB), None);
+        assert_eq!(select_format(&json!({}), 1080, 50 * MB), None);
+    }
+
+    #[test]
+    fn fallback_selector_caps_height() {
+        assert_eq!(
+            fallback_selector(720),
+            "bv*[height<=720]+ba/b[height<=720]/b"
+        );
+    }
+
+    #[test]
+    fn probe_output_is_parsed() {
+        let stdout = br#"{
+            "streams": [{ "width": 1280, "height": 720 }],
+            "format": { "duration": "212.6" }
+        }"#;
+
+        assert_eq!(
+            parse_probe_output(stdout),
+            VideoInfo {
+                width: Some(1280),
+                height: Some(720),
+                duration: Some(213),
+            }
+        );
+        assert_eq!(parse_probe_output(b"garbage"), VideoInfo::default());
+    }
+}
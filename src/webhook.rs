@@ -0,0 +1,166 @@
+use crate::telegram::{SetWebhook, TelegramClient};
+use axum::http::HeaderMap;
+use log::{info, warn};
+
+/// Header Telegram sets on every webhook request when `setWebhook` was called
+/// with a `secret_token`.
+pub(crate) const SECRET_TOKEN_HEADER: &str = "x-telegram-bot-api-secret-token";
+
+/// Returns true if the request carries the configured secret token, or if no
+/// secret is configured at all.
+pub(crate) fn secret_matches(headers: &HeaderMap, expected: Option<&str>) -> bool {
+    let Some(expected) = expected else {
+        return true;
+    };
+
+    headers
+        .get(SECRET_TOKEN_HEADER)
+        .map(|value| constant_time_eq(value.as_bytes(), expected.as_bytes()))
+        .unwrap_or(false)
+}
+
+/// Compares without short-circuiting so response timing does not reveal how
+/// much of a guessed secret was correct.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Telegram only accepts 1-256 characters from `A-Z`, `a-z`, `0-9`, `_` and `-`.
+pub(crate) fn is_valid_secret(secret: &str) -> bool {
+    (1..=256).contains(&secret.len())
+        && secret
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+/// Points the bot's webhook at `url`, asking Telegram to send `secret` with
+/// every update.
+pub(crate) async fn register_webhook(
+    telegram: &TelegramClient,
+    url: &str,
+    secret: Option<&str>,
+) -> Result<(), String> {
+    if secret.is_none() {
+        warn!("Registering webhook without a secret token; any client can post updates");
+    }
+
+    let request = SetWebhook {
+        url,
+        secret_token: secret,
+    };
+    telegram
+        .set_webhook(&request)
+        .await
+        .map_err(|e| format!("setWebhook failed: {}", e))?;
+
+    info!("Webhook registered at {}", url);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SECRET_TOKEN_HEADER, is_valid_secret, register_webhook, secret_matches};
+    use crate::telegram::TelegramClient;
+    use axum::http::{HeaderMap, HeaderValue};
+    use serde_json::json;
+    use wiremock::matchers::{body_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn headers_with_secret(secret: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(SECRET_TOKEN_HEADER, HeaderValue::from_str(secret).unwrap());
+        headers
+    }
+
+    #[test]
+    fn matching_secret_is_accepted() {
+        assert!(secret_matches(
+            &headers_with_secret("s3cret"),
+            Some("s3cret")
+        ));
+    }
+
+    #[test]
+    fn wrong_or_missing_secret_is_rejected() {
+        assert!(!secret_matches(
+            &headers_with_secret("guess"),
+            Some("s3cret")
+        ));
+        assert!(!secret_matches(
+            &headers_with_secret("s3cret-longer"),
+            Some("s3cret")
+        ));
+        assert!(!secret_matches(&HeaderMap::new(), Some("s3cret")));
+    }
+
+    #[test]
+    fn no_configured_secret_accepts_any_request() {
+        assert!(secret_matches(&HeaderMap::new(), None));
+    }
+
+    #[test]
+    fn secret_charset_follows_telegram_rules() {
+        assert!(is_valid_secret("abc_DEF-123"));
+        assert!(!is_valid_secret(""));
+        assert!(!is_valid_secret("has space"));
+        assert!(!is_valid_secret(&"a".repeat(257)));
+    }
+
+    #[tokio::test]
+    async fn register_webhook_sends_url_and_secret() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/setWebhook"))
+            .and(body_json(json!({
+                "url": "https://example.com/webhook",
+                "secret_token": "s3cret"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "ok": true,
+                "result": true
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let result = register_webhook(
+            &TelegramClient::new(&server.uri(), "TEST_TOKEN"),
+            "https://example.com/webhook",
+            Some("s3cret"),
+        )
+        .await;
+
+        assert_eq!(result, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn register_webhook_reports_api_errors() {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/setWebhook"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "ok": false,
+                "description": "Bad Request: bad webhook: HTTPS url must be provided"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let result = register_webhook(
+            &TelegramClient::new(&server.uri(), "TEST_TOKEN"),
+            "http://example.com/webhook",
+            None,
+        )
+        .await;
+
+        assert_eq!(
+            result,
+            Err("setWebhook failed: Bad Request: bad webhook: HTTPS url must be provided".into())
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use log::{error, info, warn};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const PREFIX: &str = "job-";
+/// Suffix of a directory renamed for removal, so a half-deleted directory is
+/// never mistaken for a live one.
+const REMOVING_SUFFIX: &str = ".removing";
+
+/// Directory a single job downloads into. Dropping it removes the directory
+/// with everything in it, whether the job succeeded, failed or was cancelled.
+pub(crate) struct WorkDir {
+    path: PathBuf,
+}
+
+impl WorkDir {
+    /// Creates `job-{id}` under `root`, replacing whatever an interrupted
+    /// earlier attempt of the same job left there.
+    pub(crate) fn create(root: &Path, job_id: u64) -> io::Result<Self> {
+        let path = root.join(format!("{}{}", PREFIX, job_id));
+        if path.exists() {
+            remove(&path)?;
+        }
+        fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        if let Err(e) = remove(&self.path) {
+            error!(
+                "Failed to remove work directory {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Renames the directory out of the way before deleting it, so it either
+/// exists completely or is marked for removal.
+fn remove(path: &Path) -> io::Result<()> {
+    let removing = PathBuf::from(format!("{}{}", path.display(), REMOVING_SUFFIX));
+    match fs::rename(path, &removing) {
+        Ok(()) => fs::remove_dir_all(&removing),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Removes job directories left behind by a crash. Call before jobs are
+/// resumed, since each resumed job starts over in a fresh directory.
+pub(crate) fn remove_stale(root: &Path) {
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            warn!("Failed to list {}: {}", root.display(), e);
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let is_job_dir = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with(PREFIX));
+        if !is_job_dir || !entry.path().is_dir() {
+            continue;
+        }
+        match fs::remove_dir_all(entry.path()) {
+            Ok(()) => info!("Removed stale work directory {}", entry.path().display()),
+            Err(e) => warn!("Failed to remove {}: {}", entry.path().display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WorkDir, remove_stale};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn directory_is_removed_with_its_files_on_drop() {
+        let root = TempDir::new().unwrap();
+        let work_dir = WorkDir::create(root.path(), 7).unwrap();
+        let path = work_dir.path().to_path_buf();
+        fs::write(path.join("Song.mp3"), b"audio").unwrap();
+        fs::write(path.join("1_Song.mp3"), b"part").unwrap();
+
+        drop(work_dir);
+
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(root.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn jobs_with_the_same_title_get_separate_directories() {
+        let root = TempDir::new().unwrap();
+        let first = WorkDir::create(root.path(), 1).unwrap();
+        let second = WorkDir::create(root.path(), 2).unwrap();
+        fs::write(first.path().join("Song.mp3"), b"first").unwrap();
+        fs::write(second.path().join("Song.mp3"), b"second").unwrap();
+
+        drop(second);
+
+        assert_eq!(fs::read(first.path().join("Song.mp3")).unwrap(), b"first");
+    }
+
+    #[test]
+    fn leftovers_of_earlier_runs_are_removed() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir_all(root.path().join("job-3")).unwrap();
+        fs::write(root.path().join("job-3/partial.webm.part"), b"").unwrap();
+        fs::create_dir_all(root.path().join("job-4.removing")).unwrap();
+        fs::write(root.path().join("keep.txt"), b"").unwrap();
+
+        let work_dir = WorkDir::create(root.path(), 3).unwrap();
+        assert_eq!(fs::read_dir(work_dir.path()).unwrap().count(), 0);
+        std::mem::forget(work_dir);
+
+        remove_stale(root.path());
+
+        let mut remaining = fs::read_dir(root.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(remaining, vec!["keep.txt"]);
+    }
+}
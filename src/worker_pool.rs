@@ -0,0 +1,311 @@
+use crate::AppState;
+use crate::download::run_job;
+use crate::jobs::Job;
+use crate::telegram::TelegramClient;
+use crate::telegram_status::TelegramStatusMessage;
+use log::{error, info};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use tokio::sync::{Notify, watch};
+use tokio_util::sync::CancellationToken;
+
+/// FIFO queue of jobs served by a fixed number of workers, so a burst of
+/// links never runs more than the configured number of downloads at once.
+pub(crate) struct WorkerPool {
+    queue: Mutex<VecDeque<Job>>,
+    /// Cancellation handles of the jobs workers are currently running.
+    running: Mutex<HashMap<u64, CancellationToken>>,
+    job_available: Notify,
+    /// The jobs still waiting, in order, published whenever their positions
+    /// shift so the displayed positions can be refreshed.
+    waiting: watch::Sender<Vec<Job>>,
+}
+
+#[derive(Debug)]
+pub(crate) enum Cancellation {
+    /// The job had not started yet and was taken out of the queue.
+    Dequeued(Job),
+    /// The running job was asked to stop; its worker finishes the cleanup.
+    Signalled,
+    NotFound,
+}
+
+pub(crate) fn queued_text(position: usize) -> String {
+    format!("Queued (position {})", position)
+}
+
+impl WorkerPool {
+    pub(crate) fn new() -> Self {
+        Self {
+            queue: Mutex::new(VecDeque::new()),
+            running: Mutex::new(HashMap::new()),
+            job_available: Notify::new(),
+            waiting: watch::Sender::new(Vec::new()),
+        }
+    }
+
+    /// Number of jobs waiting for a worker.
+    pub(crate) fn queued_len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// Appends a job to the back of the queue and returns its 1-based position.
+    pub(crate) fn enqueue(&self, job: Job) -> usize {
+        let position = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.push_back(job);
+            queue.len()
+        };
+        self.job_available.notify_one();
+        position
+    }
+
+    /// 1-based position of a job that is still waiting for a worker.
+    pub(crate) fn queue_position(&self, job_id: u64) -> Option<usize> {
+        let queue = self.queue.lock().unwrap();
+        queue
+            .iter()
+            .position(|job| job.id == job_id)
+            .map(|index| index + 1)
+    }
+
+    pub(crate) fn cancel(&self, job_id: u64) -> Cancellation {
+        // Lock order matches `next` so a job is always either queued or running.
+        let mut queue = self.queue.lock().unwrap();
+        if let Some(index) = queue.iter().position(|job| job.id == job_id) {
+            let job = queue.remove(index).expect("index is in bounds");
+            self.waiting.send_replace(queue.iter().cloned().collect());
+            return Cancellation::Dequeued(job);
+        }
+
+        match self.running.lock().unwrap().get(&job_id) {
+            Some(token) => {
+                token.cancel();
+                Cancellation::Signalled
+            }
+            None => Cancellation::NotFound,
+        }
+    }
+
+    /// Waits for the oldest queued job and marks it as running.
+    async fn next(&self) -> (Job, CancellationToken) {
+        loop {
+            {
+                let mut queue = self.queue.lock().unwrap();
+                if let Some(job) = queue.pop_front() {
+                    let token = CancellationToken::new();
+                    self.running.lock().unwrap().insert(job.id, token.clone());
+                    // Sent under the lock, so snapshots are published in order
+                    self.waiting.send_replace(queue.iter().cloned().collect());
+                    return (job, token);
+                }
+            }
+            self.job_available.notified().await;
+        }
+    }
+
+    fn finish(&self, job_id: u64) {
+        self.running.lock().unwrap().remove(&job_id);
+    }
+}
+
+/// Starts `count` workers that take jobs from the pool until the process exits.
+pub(crate) fn spawn_workers(state: &AppState, count: usize) {
+    info!("Starting {} download worker(s)", count);
+    tokio::spawn(refresh_queue_positions(
+        state.telegram.clone(),
+        state.pool.waiting.subscribe(),
+    ));
+    for worker_id in 1..=count {
+        let state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let (job, cancel) = state.pool.next().await;
+
+                let job_id = job.id;
+                // Run the job in its own task so a panic only fails that job.
+                if let Err(e) = tokio::spawn(run_job(state.clone(), job, cancel)).await {
+                    error!(
+                        "Worker {} crashed while running job {}: {}",
+                        worker_id, job_id, e
+                    );
+                }
+                state.pool.finish(job_id);
+            }
+        });
+    }
+}
+
+/// Keeps the status messages of waiting jobs showing their queue position.
+/// Only the latest snapshot is shown, and messages whose position did not
+/// change are left alone.
+async fn refresh_queue_positions(telegram: TelegramClient, mut waiting: watch::Receiver<Vec<Job>>) {
+    // Position last shown for each waiting job
+    let mut shown: HashMap<u64, (usize, TelegramStatusMessage)> = HashMap::new();
+    while waiting.changed().await.is_ok() {
+        let snapshot = waiting.borrow_and_update().clone();
+        let mut still_waiting = HashMap::new();
+        for (index, job) in snapshot.into_iter().enumerate() {
+            let Some(message_id) = job.status_message_id else {
+                continue;
+            };
+            let position = index + 1;
+            let (mut shown_position, status) = shown.remove(&job.id).unwrap_or_else(|| {
+                let status = TelegramStatusMessage::attach(&telegram, job.chat_id, message_id);
+                (0, status)
+            });
+            // A newer snapshot supersedes the rest of this one
+            if shown_position != position && !waiting.has_changed().unwrap_or(true) {
+                status.update(&queued_text(position)).await;
+                shown_position = position;
+            }
+            still_waiting.insert(job.id, (shown_position, status));
+        }
+        shown = still_waiting;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cancellation, WorkerPool, refresh_queue_positions};
+    use crate::jobs::{Job, JobState};
+    use crate::telegram::TelegramClient;
+    use serde_json::{Value, json};
+    use std::time::Duration;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn job(id: u64) -> Job {
+        Job {
+            id,
+            chat_id: 1,
+            user_id: 1,
+            url: format!("https://example.com/{}", id),
+            options: Default::default(),
+            state: JobState::Queued,
+            attempts: 0,
+            status_message_id: None,
+            playlist: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn jobs_are_served_in_fifo_order() {
+        let pool = WorkerPool::new();
+
+        assert_eq!(pool.enqueue(job(1)), 1);
+        assert_eq!(pool.enqueue(job(2)), 2);
+        assert_eq!(pool.enqueue(job(3)), 3);
+
+        let (first, _) = pool.next().await;
+        assert_eq!(first.id, 1);
+
+        let (second, _) = pool.next().await;
+        assert_eq!(second.id, 2);
+        assert_eq!(pool.queued_len(), 1);
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_queued_job_removes_it() {
+        let pool = WorkerPool::new();
+        pool.enqueue(job(1));
+        pool.enqueue(job(2));
+        pool.enqueue(job(3));
+
+        assert!(matches!(pool.cancel(2), Cancellation::Dequeued(job) if job.id == 2));
+        assert_eq!(pool.queue_position(3), Some(2));
+        assert!(matches!(pool.cancel(2), Cancellation::NotFound));
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_running_job_triggers_its_token() {
+        let pool = WorkerPool::new();
+        pool.enqueue(job(1));
+
+        let (_, token) = pool.next().await;
+        assert!(!token.is_cancelled());
+        assert!(matches!(pool.cancel(1), Cancellation::Signalled));
+        assert!(token.is_cancelled());
+
+        pool.finish(1);
+        assert!(matches!(pool.cancel(1), Cancellation::NotFound));
+    }
+
+    #[tokio::test]
+    async fn waiting_worker_wakes_up_on_enqueue() {
+        let pool = std::sync::Arc::new(WorkerPool::new());
+
+        let worker = {
+            let pool = pool.clone();
+            tokio::spawn(async move { pool.next().await.0.id })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        pool.enqueue(job(7));
+
+        let id = tokio::time::timeout(Duration::from_secs(1), worker)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(id, 7);
+    }
+
+    /// `(message_id, text)` of every status edit the server received.
+    async fn edits(server: &MockServer, count: usize) -> Vec<(i64, String)> {
+        for _ in 0..100 {
+            let requests = server.received_requests().await.unwrap();
+            if requests.len() >= count {
+                return requests
+                    .iter()
+                    .map(|request| {
+                        let body: Value = serde_json::from_slice(&request.body).unwrap();
+                        (
+                            body["message_id"].as_i64().unwrap(),
+                            body["text"].as_str().unwrap().to_string(),
+                        )
+                    })
+                    .collect();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("expected {} status edits", count);
+    }
+
+    #[tokio::test]
+    async fn only_changed_queue_positions_are_edited() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/botTEST_TOKEN/editMessageText"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "ok": true,
+                "result": { "message_id": 1 }
+            })))
+            .mount(&server)
+            .await;
+        let pool = WorkerPool::new();
+        for id in 1..=4 {
+            pool.enqueue(Job {
+                status_message_id: Some(10 + id as i64),
+                ..job(id)
+            });
+        }
+        tokio::spawn(refresh_queue_positions(
+            TelegramClient::new(&server.uri(), "TEST_TOKEN"),
+            pool.waiting.subscribe(),
+        ));
+
+        pool.next().await;
+        let shifted = vec![
+            (12, "Queued (position 1)".to_string()),
+            (13, "Queued (position 2)".to_string()),
+            (14, "Queued (position 3)".to_string()),
+        ];
+        assert_eq!(edits(&server, 3).await, shifted);
+
+        // Removing the last job moves nobody
+        pool.cancel(4);
+        pool.next().await;
+        let mut expected = shifted;
+        expected.push((13, "Queued (position 1)".to_string()));
+        assert_eq!(edits(&server, 4).await, expected);
+    }
+}
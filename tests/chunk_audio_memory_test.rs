@@ -0,0 +1,75 @@
+mod common;
+
+use common::write_mp3;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tempfile::TempDir;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use yt_dl_service::chunk_audio::split_mp3;
+
+// ===== Allocation Tracking =====
+
+/// Counts heap bytes across all threads, including the blocking pool that
+/// runs tokio's file I/O. This file holds a single test so nothing else
+/// allocates while it measures.
+struct TrackingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let now = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(now, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+/// Peak bytes allocated while `f` runs, above the starting level
+async fn peak_allocation<F: std::future::Future>(f: F) -> (F::Output, usize) {
+    let start = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(start, Ordering::Relaxed);
+    let output = f.await;
+    (output, PEAK.load(Ordering::Relaxed).saturating_sub(start))
+}
+
+// ===== Memory Use =====
+
+#[tokio::test]
+async fn test_split_mp3_memory_stays_bounded() {
+    const PEAK_LIMIT: usize = 8 * 1024 * 1024;
+
+    let temp_dir = TempDir::new().unwrap();
+    let framed = temp_dir.path().join("framed.mp3");
+    write_mp3(&framed, b"Xing", 130_000);
+    let raw = temp_dir.path().join("raw.mp3");
+    let mut file = File::create(&raw).await.unwrap();
+    let block = vec![0u8; 1024 * 1024];
+    for _ in 0..60 {
+        file.write_all(&block).await.unwrap();
+    }
+    file.sync_all().await.unwrap();
+    drop(file);
+
+    // Both the frame-aware path and the byte-offset fallback copy ~49MB parts
+    for path in [&framed, &raw] {
+        let (chunks, peak) = peak_allocation(split_mp3(path.to_str().unwrap())).await;
+        let chunks = chunks.unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert!(
+            peak < PEAK_LIMIT,
+            "splitting {} peaked at {} bytes",
+            path.display(),
+            peak
+        );
+    }
+}
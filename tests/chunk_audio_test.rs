@@ -1,9 +1,14 @@
+mod common;
+
+use common::{FRAME_HEADER, FRAME_LEN, id3_tag, write_mp3};
 use std::fs;
 use tempfile::TempDir;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use yt_dl_service::chunk_audio::{
-    ChunkInfo, cleanup_chunks, extract_original_filename, needs_chunking, split_mp3,
+    ChunkInfo, SplitConfig, SplitMode, cleanup_chunks, extract_original_filename, needs_chunking,
+    quietest_boundary, segment_secs, split_by_duration, split_by_duration_with, split_mp3,
+    split_mp3_with,
 };
 
 // ===== Basic Unit Tests =====
@@ -137,16 +142,20 @@ async fn test_cleanup_chunks() {
         path: temp_dir.path().join("1_test.mp3"),
         index: 1,
         size: 49 * 1024 * 1024,
+        start_secs: None,
+        duration_secs: None,
     };
     let chunk2 = ChunkInfo {
         path: temp_dir.path().join("2_test.mp3"),
         index: 2,
         size: 2 * 1024 * 1024,
+        start_secs: None,
+        duration_secs: None,
     };
 
     // Create dummy files
-    fs::write(&chunk1.path, &[0u8; 100]).unwrap();
-    fs::write(&chunk2.path, &[0u8; 100]).unwrap();
+    fs::write(&chunk1.path, [0u8; 100]).unwrap();
+    fs::write(&chunk2.path, [0u8; 100]).unwrap();
 
     assert!(chunk1.path.exists());
     assert!(chunk2.path.exists());
@@ -184,3 +193,200 @@ async fn test_exact_chunk_boundaries() {
     assert_eq!(chunks[0].size, 49 * 1024 * 1024);
     assert_eq!(chunks[1].size, 49 * 1024 * 1024);
 }
+
+// ===== MP3 Frame Splitting =====
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes.try_into().unwrap())
+}
+
+#[tokio::test]
+async fn test_split_mp3_parts_have_tag_and_xing_header() {
+    let temp_dir = TempDir::new().unwrap();
+    let test_file = temp_dir.path().join("frames.mp3");
+    let total_frames = 130_000; // ~54MB
+    write_mp3(&test_file, b"Xing", total_frames);
+
+    let chunks = split_mp3(test_file.to_str().unwrap()).await.unwrap();
+    assert_eq!(chunks.len(), 2);
+
+    let tag = id3_tag();
+    let mut frames_seen = 0;
+    for chunk in &chunks {
+        let data = fs::read(&chunk.path).unwrap();
+        assert_eq!(data.len() as u64, chunk.size);
+        assert!(chunk.size <= 49 * 1024 * 1024);
+
+        // Every part starts with the tag, then a Xing frame for that part
+        assert_eq!(&data[..tag.len()], tag.as_slice());
+        let xing = &data[tag.len()..tag.len() + FRAME_LEN];
+        assert_eq!(&xing[..4], &FRAME_HEADER);
+        assert_eq!(&xing[36..40], b"Xing");
+        let frames = be_u32(&xing[44..48]) as usize;
+        let bytes = be_u32(&xing[48..52]) as usize;
+        assert_eq!(bytes, data.len() - tag.len());
+        assert_eq!(frames * FRAME_LEN + FRAME_LEN, bytes);
+
+        // The audio starts and ends on frame boundaries
+        assert_eq!(&data[tag.len() + FRAME_LEN..][..4], &FRAME_HEADER);
+        frames_seen += frames;
+
+        let expected_secs = frames as f64 * 1152.0 / 44100.0;
+        assert!((chunk.duration_secs.unwrap() - expected_secs).abs() < 1e-6);
+    }
+    assert_eq!(frames_seen, total_frames);
+
+    assert_eq!(chunks[0].start_secs, Some(0.0));
+    let second_start = chunks[1].start_secs.unwrap();
+    assert!((second_start - chunks[0].duration_secs.unwrap()).abs() < 1e-6);
+}
+
+#[tokio::test]
+async fn test_split_mp3_replaces_vbri_header() {
+    let temp_dir = TempDir::new().unwrap();
+    let test_file = temp_dir.path().join("vbri.mp3");
+    write_mp3(&test_file, b"VBRI", 130_000);
+
+    let chunks = split_mp3(test_file.to_str().unwrap()).await.unwrap();
+    assert_eq!(chunks.len(), 2);
+
+    let tag_len = id3_tag().len();
+    for chunk in &chunks {
+        let data = fs::read(&chunk.path).unwrap();
+        let first_frame = &data[tag_len..tag_len + FRAME_LEN];
+        assert_eq!(&first_frame[36..40], b"Xing");
+        assert!(
+            !data[..tag_len + 2 * FRAME_LEN]
+                .windows(4)
+                .any(|w| w == b"VBRI")
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_split_mp3_follows_configured_upload_limit() {
+    let temp_dir = TempDir::new().unwrap();
+    let test_file = temp_dir.path().join("limit.mp3");
+    write_mp3(&test_file, b"Xing", 130_000); // ~54MB
+
+    // A local Bot API server takes the whole file
+    let local = SplitConfig {
+        upload_limit: 2000 * 1024 * 1024,
+        mode: SplitMode::Size,
+    };
+    let chunks = split_mp3_with(test_file.to_str().unwrap(), local)
+        .await
+        .unwrap();
+    assert!(chunks.is_empty());
+
+    // A smaller limit gives more, smaller parts
+    let small = SplitConfig {
+        upload_limit: 20 * 1024 * 1024,
+        mode: SplitMode::Size,
+    };
+    let chunks = split_mp3_with(test_file.to_str().unwrap(), small)
+        .await
+        .unwrap();
+    assert_eq!(chunks.len(), 3);
+    assert!(chunks.iter().all(|chunk| chunk.size <= small.chunk_size()));
+}
+
+#[tokio::test]
+async fn test_split_mp3_with_nonexistent_file() {
+    let local = SplitConfig {
+        upload_limit: 2000 * 1024 * 1024,
+        mode: SplitMode::Silence,
+    };
+    let result = split_mp3_with("/nonexistent/file.mp3", local).await;
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_chunk_size_scales_with_upload_limit() {
+    assert_eq!(SplitConfig::default().chunk_size(), 49 * 1024 * 1024);
+    let local = SplitConfig {
+        upload_limit: 2000 * 1024 * 1024,
+        mode: SplitMode::Size,
+    };
+    assert_eq!(local.chunk_size(), 1960 * 1024 * 1024);
+    assert!(!local.needs_chunking(1024 * 1024 * 1024));
+}
+
+// ===== Silence Detection =====
+
+#[test]
+fn test_quietest_boundary_finds_pause() {
+    // 5 seconds of loud audio at 8kHz with a pause from 3.0s to 3.5s
+    let mut pcm = vec![8000i16; 5 * 8000];
+    pcm[24_000..28_000].fill(0);
+    // A frame boundary every 26ms, as for 44.1kHz MP3
+    let boundaries: Vec<f64> = (0..192).map(|i| i as f64 * 0.026).collect();
+
+    let index = quietest_boundary(&pcm, 8000, &boundaries).unwrap();
+
+    // The whole 0.3s span around the cut lies inside the pause
+    let cut = boundaries[index];
+    assert!((3.15..=3.35).contains(&cut), "cut at {}s", cut);
+}
+
+#[test]
+fn test_quietest_boundary_prefers_later_cut_on_ties() {
+    let pcm = vec![0i16; 8000];
+    let boundaries = [0.0, 0.25, 0.5, 0.75];
+
+    assert_eq!(quietest_boundary(&pcm, 8000, &boundaries), Some(3));
+    assert_eq!(quietest_boundary(&[], 8000, &boundaries), None);
+}
+
+#[test]
+fn test_segment_duration_stays_under_chunk_size() {
+    // 200MB over 100 minutes = ~35KB/s; 90% of 49MB is ~1317s
+    let size = 200 * 1024 * 1024;
+    let secs = segment_secs(SplitConfig::default().chunk_size(), size, 6000.0);
+    let bytes_per_sec = size as f64 / 6000.0;
+
+    assert!(secs * bytes_per_sec <= (49 * 1024 * 1024) as f64);
+    assert!(secs * bytes_per_sec >= (40 * 1024 * 1024) as f64);
+}
+
+#[test]
+fn test_segment_duration_has_lower_bound() {
+    let chunk_size = SplitConfig::default().chunk_size();
+    assert_eq!(segment_secs(chunk_size, u64::MAX, 1.0), 1.0);
+    assert_eq!(segment_secs(chunk_size, 0, 10.0), 10.0);
+}
+
+#[tokio::test]
+async fn test_split_by_duration_small_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let test_file = temp_dir.path().join("small.m4a");
+    fs::write(&test_file, vec![0u8; 1024]).unwrap();
+
+    // Under the limit nothing is split and ffmpeg is never invoked
+    let chunks = split_by_duration(test_file.to_str().unwrap())
+        .await
+        .unwrap();
+    assert!(chunks.is_empty());
+}
+
+#[tokio::test]
+async fn test_split_by_duration_follows_configured_upload_limit() {
+    let temp_dir = TempDir::new().unwrap();
+    let test_file = temp_dir.path().join("large.m4a");
+    let file = std::fs::File::create(&test_file).unwrap();
+    file.set_len(60 * 1024 * 1024).unwrap(); // Over the default limit
+    drop(file);
+
+    // A local Bot API server takes the whole file, so ffmpeg is never invoked
+    let local = SplitConfig {
+        upload_limit: 2000 * 1024 * 1024,
+        mode: SplitMode::Size,
+    };
+    let chunks = split_by_duration_with(test_file.to_str().unwrap(), local)
+        .await
+        .unwrap();
+    assert!(chunks.is_empty());
+
+    let result = split_by_duration_with("/nonexistent/file.m4a", local).await;
+    assert!(result.is_err());
+}
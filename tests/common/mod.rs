@@ -0,0 +1,36 @@
+//! MP3 fixtures shared by the chunk_audio integration tests
+
+use std::fs;
+
+/// MPEG-1 Layer III, 128 kbit/s, 44.1 kHz, stereo: 417-byte frames of 1152 samples
+pub const FRAME_HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0x00];
+pub const FRAME_LEN: usize = 417;
+
+/// An ID3v2.3 tag with a 100-byte body
+pub fn id3_tag() -> Vec<u8> {
+    let mut tag = b"ID3\x03\x00\x00\x00\x00\x00\x64".to_vec();
+    tag.extend([0u8; 100]);
+    tag
+}
+
+/// A frame carrying `marker` where a Xing (offset 36) or VBRI header lives
+fn info_frame(marker: &[u8; 4]) -> Vec<u8> {
+    let mut frame = vec![0u8; FRAME_LEN];
+    frame[..4].copy_from_slice(&FRAME_HEADER);
+    frame[36..40].copy_from_slice(marker);
+    frame
+}
+
+/// Writes tag + info frame + `frames` audio frames
+pub fn write_mp3(path: &std::path::Path, marker: &[u8; 4], frames: usize) {
+    let mut audio_frame = vec![0u8; FRAME_LEN];
+    audio_frame[..4].copy_from_slice(&FRAME_HEADER);
+
+    let mut data = id3_tag();
+    data.extend(info_frame(marker));
+    data.reserve(frames * FRAME_LEN);
+    for _ in 0..frames {
+        data.extend_from_slice(&audio_frame);
+    }
+    fs::write(path, data).unwrap();
+}